@@ -0,0 +1,16 @@
+//! Stand-alone elevator simulator. Run with `cargo run --bin simulator -- --port 15658`.
+#![allow(clippy::needless_return)]
+
+use elevator::simulator::server::{self, SimConfig};
+
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config = match SimConfig::from_args(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    return server::run(config);
+}
@@ -1,152 +1,262 @@
 use crossbeam_channel as cbc;
+use std::collections::{BTreeMap, HashMap};
 use std::time;
 use std::thread::*;
 
-use crate::local_elevator::elevio::poll::{CallButton, CAB};
+use crate::local_elevator::elevio::poll::{CallButton, CAB, HALL_DOWN, HALL_UP};
 use crate::local_elevator::elevio::elev::HardwareCommand;
-use crate::local_elevator::fsm::elevatorfsm::{ElevatorInfo, State};
-use crate::local_elevator::fsm::order_list::{OrderList, OrderType};
-use crate::util::constants::{MAX_NUM_ELEV, ELEV_NUM_FLOORS};
-use crate::util::constants::ID as LOCAL_ID;
-
-
+use crate::local_elevator::fsm::elevatorfsm::{ElevatorInfo, Recall, RecallSignal, State};
+use crate::local_elevator::fsm::order_list::OrderList;
+use crate::global_elevator_info::elev_info_updater::RemoteUpdate;
+use crate::global_elevator_info::hall_orders::HallOrders;
+use crate::global_elevator_info::failure_detector::PeerHealth;
+use crate::order_assigner::cost_function;
+use crate::order_assigner::takeover::Takeovers;
+use crate::util::config::Config;
+
+
+/// What we know about every elevator we are connected to, including our own, keyed by node id.
+/// Elevators join and leave as they are seen on the network, there is no fixed number of them.
 #[derive(Clone, Debug)]
 pub struct ConnectedElevatorInfo {
-    connected_elevators: Vec<Option<ElevatorInfo>>,
+    local_id: usize,
+    num_floors: u8,
+    connected_elevators: BTreeMap<usize, ElevatorInfo>,
+    hall_orders: HallOrders,
+    peer_health: HashMap<usize, PeerHealth>,
+    /// Our hall orders that another car has taken, and the local elevator is about to drop
+    handed_over: Vec<CallButton>,
+    /// Hall orders we have sent to another elevator, by its id, until it reports them active or we give up on it
+    assigning: Vec<(usize, CallButton)>,
 }
 
 impl ConnectedElevatorInfo {
-    fn new(local_elev: ElevatorInfo, max_number_of_elevators: usize) -> ConnectedElevatorInfo {
-
-        let mut connected_elevs: Vec<Option<ElevatorInfo>> = Vec::new();
-        connected_elevs.resize_with(max_number_of_elevators, || None);
-        connected_elevs[LOCAL_ID] = Some(local_elev.clone());
+    pub(crate) fn new(local_elev: ElevatorInfo, config: &Config) -> ConnectedElevatorInfo {
         ConnectedElevatorInfo {
-            connected_elevators: connected_elevs,
+            local_id: config.id(),
+            num_floors: config.num_floors,
+            connected_elevators: BTreeMap::from([(config.id(), local_elev)]),
+            hall_orders: HallOrders::new(config.num_floors),
+            peer_health: HashMap::new(),
+            handed_over: Vec::new(),
+            assigning: Vec::new(),
         }
     }
 
-    /// Updates global info with the newest info received from remote elevators.
-    fn update_remote_elevator_info(&mut self, remote_update: Vec<ElevatorInfo>) -> Vec<CallButton> {
+    /// Updates global info with the newest info received from remote elevators. Every elevator is the only one that
+    /// changes its own orders, so its info is taken as it is. What the building agrees on is kept in `hall_orders`.
+    /// Returns the hall orders of the elevators that were lost, or that can no longer serve them, with the id of the elevator.
+    pub(crate) fn update_remote_elevator_info(&mut self, remote_update: Vec<ElevatorInfo>) -> Vec<(usize, CallButton)> {
 
-        let mut new_connected_elev_info: Vec<Option<ElevatorInfo>> = Vec::new();
-        new_connected_elev_info.resize_with(MAX_NUM_ELEV, || None);
-        
-        let mut fix_len_remote_elev_update = new_connected_elev_info.clone();
-        let prev_connected_elev_info = self.get_connected_elevators();
-        new_connected_elev_info[LOCAL_ID] = self.get_local_elevator_info();
-        let mut lost_orders: Vec<CallButton> = Vec::new();
-
-        for elev in remote_update.iter() {
-            fix_len_remote_elev_update[elev.get_id()] = Some(elev.clone());
+        let mut new_connected_elev_info: BTreeMap<usize, ElevatorInfo> = BTreeMap::new();
+        if let Some(local_info) = self.get_local_elevator_info() {
+            new_connected_elev_info.insert(self.local_id, local_info);
         }
-        
-        for i in 0..MAX_NUM_ELEV {
-            if i != LOCAL_ID {
-                let mut remote_info: ElevatorInfo;
-                let existing_info: ElevatorInfo;
-                match prev_connected_elev_info[i].as_ref() {
-                    None => {
-                        new_connected_elev_info[i] = fix_len_remote_elev_update[i].clone();
-                    },
-                    Some(vl) => {
-                        existing_info = vl.clone();
-                        match fix_len_remote_elev_update[i].as_ref() {
-                            None => {
-                                lost_orders.append(&mut assign_orders_locally(existing_info.responsible_orders));
-                                new_connected_elev_info[i] = None;
-                                
-                            },
-                            Some(vr) => {
-                                remote_info = vr.clone();
-                                if (existing_info.state != State::MovTimedOut && remote_info.state == State::MovTimedOut) 
-                                || (existing_info.state != State::ObstrTimedOut && remote_info.state == State::ObstrTimedOut) {
-                                    lost_orders.append(&mut assign_orders_locally(existing_info.responsible_orders.clone()));
-                                }
-                                remote_info.responsible_orders = merge_remote_orders(existing_info.clone().responsible_orders.clone(), remote_info.clone().responsible_orders.clone());
-                                new_connected_elev_info[i] = Some(remote_info);
-                            }
-                        }
-                    }
+        let mut lost_orders: Vec<(usize, CallButton)> = Vec::new();
+
+        let local_id = self.local_id;
+        for remote_info in remote_update.into_iter().filter(|elev| elev.get_id() != local_id) {
+            if remote_info.responsible_orders.up_queue.len() != usize::from(self.num_floors) {
+                println!("Ignoring elevator {}, it has a different number of floors", remote_info.id);
+                continue;
+            }
+            if let Some(existing_info) = self.connected_elevators.get(&remote_info.id) {
+                if (existing_info.state != State::MovTimedOut && remote_info.state == State::MovTimedOut) 
+                || (existing_info.state != State::ObstrTimedOut && remote_info.state == State::ObstrTimedOut)
+                || (!matches!(existing_info.state, State::EmergencyStop { .. }) && matches!(remote_info.state, State::EmergencyStop { .. })) {
+                    lost_orders.extend(self.orders_of(existing_info).into_iter().map(|btn| (remote_info.id, btn)));
                 }
+                lost_orders.extend(self.released_by(existing_info, &remote_info).into_iter().map(|btn| (remote_info.id, btn)));
+            }
+            self.assigning.retain(|&(id, btn)| id != remote_info.id || !remote_info.responsible_orders.is_active(btn));
+            new_connected_elev_info.insert(remote_info.id, remote_info);
+        }
+
+        for (id, existing_info) in self.connected_elevators.iter() {
+            if !new_connected_elev_info.contains_key(id) {
+                lost_orders.extend(self.orders_of(existing_info).into_iter().map(|btn| (*id, btn)));
             }
         }
+        self.assigning.retain(|(id, _)| new_connected_elev_info.contains_key(id));
         println!("{:#?}", new_connected_elev_info.clone());
         self.connected_elevators = new_connected_elev_info;
         return lost_orders;
     }
 
-    fn update_local_elevator_info(&mut self, local_update: ElevatorInfo) {
-        self.connected_elevators[LOCAL_ID] = Some(local_update);
+    /// A hall order has been served when it leaves our list entirely as the door closes at its floor,
+    /// unless we handed it over to a better car. A full car passes its orders when it arrives, see `released_by`.
+    /// An order that only goes back to pending, e.g. on a motor timeout or the stop button, is still waiting.
+    /// Entering recall cancels every order, and the lights go off as if they were served.
+    pub(crate) fn update_local_elevator_info(&mut self, local_update: ElevatorInfo) {
+        if let Some(previous) = self.get_local_elevator_info() {
+            let recall_started = previous.recall == Recall::Off && local_update.recall != Recall::Off;
+            for f in 0..self.num_floors {
+                for c in 0..CAB {
+                    let btn = CallButton{floor: f, call: c};
+                    let had_order = previous.responsible_orders.is_active(btn) || previous.responsible_orders.is_pending(btn);
+                    let left_list = !local_update.responsible_orders.is_active(btn) && !local_update.responsible_orders.is_pending(btn);
+                    let door_open_here = previous.state == State::DoorOpen && previous.floor == f && local_update.floor == f;
+                    if had_order && left_list && !self.handed_over.contains(&btn)
+                        && (door_open_here || recall_started) {
+                        self.hall_orders.serve(btn);
+                    }
+                }
+            }
+        }
+        self.handed_over.retain(|btn| local_update.responsible_orders.is_active(*btn));
+        self.connected_elevators.insert(self.local_id, local_update);
+    }
+
+    /// Another car has taken our hall order. Returns false if it is not ours anymore, e.g. because we just served it.
+    fn hand_over(&mut self, btn: CallButton) -> bool {
+        if !self.is_active(self.local_id, btn) {
+            return false;
+        }
+        self.handed_over.push(btn);
+        return true;
+    }
+
+    /// The ids of the elevators we are connected to, including our own
+    fn connected_ids(&self) -> Vec<usize> {
+        return self.connected_elevators.keys().copied().collect();
+    }
+
+    /// Acknowledges new hall orders, and confirms those everyone has seen. Returns true if the hall orders changed.
+    fn acknowledge_hall_orders(&mut self) -> bool {
+        let connected_ids = self.connected_ids();
+        return self.hall_orders.acknowledge(self.local_id, &connected_ids);
+    }
+
+    /// Takes in the hall orders of another node. Returns true if ours changed.
+    pub(crate) fn merge_hall_orders(&mut self, remote_hall_orders: &HallOrders) -> bool {
+        let before = self.get_hall_orders();
+        self.hall_orders.merge(remote_hall_orders);
+        self.acknowledge_hall_orders();
+        return self.hall_orders != before;
+    }
+
+    pub fn get_hall_orders(&self) -> HallOrders {
+        return self.hall_orders.clone();
+    }
+
+    /// The hall orders a full car passed when it arrived at their floor, going from `before` to `after`.
+    /// An order another car already has was handed over to it, and is not counted.
+    fn released_by(&self, before: &ElevatorInfo, after: &ElevatorInfo) -> Vec<CallButton> {
+        if before.state != State::Moving || !after.is_full() {
+            return Vec::new();
+        }
+        let mut released = Vec::new();
+        for call in [HALL_UP, HALL_DOWN] {
+            let btn = CallButton { floor: after.floor, call };
+            let taken = self.connected_elevators.values().any(|elev| elev.id != after.id && elev.responsible_orders.is_active(btn));
+            if before.responsible_orders.is_active(btn) && !after.responsible_orders.is_active(btn) && !taken {
+                released.push(btn);
+            }
+        }
+        return released;
+    }
+
+    /// The hall orders of `elev`, and those we are sending it
+    fn orders_of(&self, elev: &ElevatorInfo) -> Vec<CallButton> {
+        let mut orders = assign_orders_locally(elev.responsible_orders.clone());
+        for &(id, btn) in self.assigning.iter() {
+            if id == elev.id && !orders.contains(&btn) {
+                orders.push(btn);
+            }
+        }
+        return orders;
     }
 
     fn set_to_pending(&mut self, should_set: bool, id: usize, button: CallButton) {
-        let mut elev_info: ElevatorInfo;
-        match self.connected_elevators[id].as_ref() {
-            Some(v) => elev_info = v.clone(),
-            None => return,
+        self.assigning.retain(|&assign| assign != (id, button));
+        if should_set && self.connected_elevators.contains_key(&id) && !self.is_active(id, button) {
+            self.assigning.push((id, button));
         }
-        elev_info.responsible_orders.set_pending(should_set, button);
-        self.connected_elevators[id] = Some(elev_info);
     }
 
+    /// Says if we have sent the order to `id`, and it has not reported it active yet
     pub fn is_pending(&self, id: usize, button: CallButton) -> bool {
-        let elev_info: ElevatorInfo;
-        match self.connected_elevators[id].as_ref() {
-            Some(v) => elev_info = v.clone(),
-            None => return false,
-        }
-        return elev_info.responsible_orders.is_pending(button);
+        return self.assigning.contains(&(id, button));
     }
 
     pub fn is_active(&self, id: usize, button: CallButton) -> bool {
-        let elev_info: ElevatorInfo;
-        match self.connected_elevators[id].as_ref() {
-            Some(v) => elev_info = v.clone(),
-            None => return false,
-        }
-        return elev_info.responsible_orders.is_active(button);
+        return self.connected_elevators.get(&id).is_some_and(|elev| elev.responsible_orders.is_active(button));
     }
 
+    /// The hall lights show the confirmed hall orders, the cab lights our own cab orders
     pub fn get_orders_for_lights(&self) -> OrderList {
-        let mut order_lights: OrderList = OrderList::new(ELEV_NUM_FLOORS);
-        for entry in self.connected_elevators.iter().cloned() {
-            match entry {
-                Some(elev) => {
-                    order_lights =
-                        merge_remote_active(order_lights.clone(), elev.clone().responsible_orders.clone())
+        let mut order_lights: OrderList = OrderList::new(self.num_floors);
+        for f in 0..self.num_floors {
+            for c in 0..CAB {
+                let btn = CallButton{floor: f, call: c};
+                if self.hall_orders.is_confirmed(btn) {
+                    order_lights.set_active(btn);
                 }
-                None => {}
             }
         }
-        match self.get_local_elevator_info() {
-            Some(v) => order_lights.inside_queue = v.responsible_orders.clone().inside_queue.clone(),
-            None => {}
+        if let Some(v) = self.get_local_elevator_info() {
+            order_lights.inside_queue = v.responsible_orders.inside_queue;
         }
         return order_lights;
     }
 
-    pub fn get_connected_elevators(&self) -> Vec<Option<ElevatorInfo>> {
-        return self.clone().connected_elevators.clone();
+    /// The newest recall signal known by any of the connected elevators
+    pub fn get_newest_recall_signal(&self) -> RecallSignal {
+        let mut newest = RecallSignal::default();
+        for elev in self.connected_elevators.values() {
+            if elev.recall_signal.is_newer_than(&newest) {
+                newest = elev.recall_signal;
+            }
+        }
+        return newest;
+    }
+
+    /// Every connected elevator, including our own, by increasing id
+    pub fn get_connected_elevators(&self) -> Vec<ElevatorInfo> {
+        return self.connected_elevators.values().cloned().collect();
+    }
+
+    /// How the failure detector sees the elevator. We are always connected to ourselves, and to those we have
+    /// just heard from for the first time.
+    pub fn get_peer_health(&self, id: usize) -> PeerHealth {
+        if id == self.local_id {
+            return PeerHealth::Connected;
+        }
+        if !self.connected_elevators.contains_key(&id) {
+            return PeerHealth::Lost;
+        }
+        return self.peer_health.get(&id).copied().unwrap_or(PeerHealth::Connected);
     }
 
     pub fn get_local_elevator_info(&self) -> Option<ElevatorInfo> {
-        return self.clone().connected_elevators[LOCAL_ID]
-            .clone();
+        return self.connected_elevators.get(&self.local_id).cloned();
+    }
+
+    pub fn get_local_id(&self) -> usize {
+        return self.local_id;
+    }
+
+    pub fn get_num_floors(&self) -> u8 {
+        return self.num_floors;
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn connected_elevator_info(
     local_update: cbc::Receiver<ElevatorInfo>, 
-    remote_update: cbc::Receiver<Vec<ElevatorInfo>>,
+    remote_update: cbc::Receiver<RemoteUpdate>,
     set_pending: cbc::Receiver<(bool, usize, CallButton)>, 
+    new_hall_orders: cbc::Receiver<CallButton>,
     global_info_update: cbc::Sender<ConnectedElevatorInfo>,
-    assign_orders_locally_tx: cbc::Sender<CallButton>) {
+    hall_orders_tx: cbc::Sender<HallOrders>,
+    assign_orders_locally_tx: cbc::Sender<CallButton>,
+    released_orders: cbc::Receiver<CallButton>,
+    handed_over: cbc::Receiver<CallButton>,
+    drop_order_tx: cbc::Sender<CallButton>,
+    config: &Config) {
 
-    let mut global_info: ConnectedElevatorInfo;
-    
     let initial_info = local_update.recv().unwrap();
-    global_info = ConnectedElevatorInfo::new(initial_info, MAX_NUM_ELEV);
+    let mut global_info = ConnectedElevatorInfo::new(initial_info, config);
 
     let (reassign_orders_tx, reassign_orders_rx) = cbc::unbounded::<Vec<CallButton>>();
 
@@ -164,24 +274,75 @@ pub fn connected_elevator_info(
     });
 
     let ticker = cbc::tick(time::Duration::from_millis(5000));
+    let mut takeovers = Takeovers::new();
 
     loop {
+        let next_takeover = match takeovers.next_deadline() {
+            Some(deadline) => cbc::at(deadline),
+            None => cbc::never(),
+        };
         cbc::select! {
             recv(local_update) -> a => {
                 let local_info = a.unwrap();
                 global_info.update_local_elevator_info(local_info);
+                global_info.acknowledge_hall_orders();
+                hall_orders_tx.send(global_info.get_hall_orders()).unwrap();
+                global_info_update.send(global_info.clone()).unwrap();
+            },
+            recv(new_hall_orders) -> a => {
+                global_info.hall_orders.press(a.unwrap());
+                global_info.acknowledge_hall_orders();
+                hall_orders_tx.send(global_info.get_hall_orders()).unwrap();
                 global_info_update.send(global_info.clone()).unwrap();
             },
             recv(remote_update) -> a => {
-                let remote_info = a.unwrap();
+                let remote_info = match a.unwrap() {
+                    RemoteUpdate::Peers(peers) => peers,
+                    RemoteUpdate::HallOrders(remote_hall_orders) => {
+                        if global_info.merge_hall_orders(&remote_hall_orders) {
+                            hall_orders_tx.send(global_info.get_hall_orders()).unwrap();
+                            global_info_update.send(global_info.clone()).unwrap();
+                        }
+                        continue;
+                    }
+                    RemoteUpdate::Health(peer_health) => {
+                        global_info.peer_health = peer_health;
+                        global_info_update.send(global_info.clone()).unwrap();
+                        continue;
+                    }
+                };
                 let lost_orders = global_info.update_remote_elevator_info(remote_info);
-                reassign_orders_tx.send(lost_orders).unwrap();
+                // Someone left, so we may be the last one to see a new hall order
+                if global_info.acknowledge_hall_orders() {
+                    hall_orders_tx.send(global_info.get_hall_orders()).unwrap();
+                }
+                // Every survivor ranks the orphaned orders the same way, and only the first in line takes them now
+                let local_orders = takeovers.orphaned(&global_info, lost_orders, config);
+                reassign_orders_tx.send(local_orders).unwrap();
                 global_info_update.send(global_info.clone()).unwrap()
             },
+            recv(next_takeover) -> _ => {
+                reassign_orders_tx.send(takeovers.check(&global_info)).unwrap();
+            },
             recv(ticker) -> _ => {
                 //println!("{:#?}", global_info.clone());
                 //global_info_update.send(global_info.clone()).unwrap();
             },
+            // Orders the local elevator passed because it is full. The others see it in our elevator info, and take it
+            // over like the orders of a failed car, with us left out. If none of them can, we keep it and try again.
+            recv(released_orders) -> a => {
+                let btn = a.unwrap();
+                if cost_function::rank_for_takeover(&global_info, btn, &[global_info.local_id], config).is_empty() {
+                    reassign_orders_tx.send(vec![btn]).unwrap();
+                }
+            },
+            // Noted before the local elevator drops the order, so it is not taken as served
+            recv(handed_over) -> a => {
+                let btn = a.unwrap();
+                if global_info.hand_over(btn) {
+                    drop_order_tx.send(btn).unwrap();
+                }
+            },
             recv(set_pending) -> a => {
                 let (should_set,id, btn) = a.unwrap();
                 global_info.set_to_pending(should_set, id, btn);
@@ -193,9 +354,10 @@ pub fn connected_elevator_info(
 
 pub fn set_order_lights(
     global_info_rx: cbc::Receiver<ConnectedElevatorInfo>, 
-    set_lights_tx: cbc::Sender<HardwareCommand>) {
+    set_lights_tx: cbc::Sender<HardwareCommand>,
+    config: &Config) {
 
-    let elev_num_floors = 4;
+    let elev_num_floors = config.num_floors;
     let mut old_lights: OrderList = OrderList::new(elev_num_floors);
     loop {
         cbc::select! {
@@ -225,7 +387,7 @@ fn assign_orders_locally(orders_to_assign: OrderList) -> Vec<CallButton> {
         let mut button;
         for c in 0..=2 {
             if c != CAB {
-                button = CallButton{floor: f as u8, call: c as u8};
+                button = CallButton{floor: f as u8, call: c};
                 if orders_to_assign.is_active(button) || orders_to_assign.is_pending(button) {
                     call_buttons_to_assign.push(button);
                 }
@@ -236,68 +398,65 @@ fn assign_orders_locally(orders_to_assign: OrderList) -> Vec<CallButton> {
 }
 
 
-fn merge_remote_active(local_order_info: OrderList, remote_orders: OrderList) -> OrderList {
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local_elevator::elevio::elev::DIRN_UP;
+    use crate::local_elevator::fsm::elevatorfsm::{step, Event};
 
-    let n_floors: usize = local_order_info.up_queue.len();
-    let mut new_order_list: OrderList = OrderList::new(n_floors as u8);
-    if n_floors != remote_orders.up_queue.len() {
-        panic!("Tried to merge elevator orders of different lengths :(");
-    }
+    const UP_AT_2: CallButton = CallButton { floor: 2, call: HALL_UP };
 
-    for i in 0..=n_floors - 1 {
-        if local_order_info.up_queue[i] == OrderType::Active
-            || remote_orders.up_queue[i] == OrderType::Active
-        {
-            new_order_list.up_queue[i] = OrderType::Active
-        }
-        if local_order_info.down_queue[i] == OrderType::Active
-            || remote_orders.down_queue[i] == OrderType::Active
-        {
-            new_order_list.down_queue[i] = OrderType::Active
-        }
+    fn config() -> Config {
+        return Config { id: Some(1), ..Default::default() };
     }
-    return new_order_list;
-}
 
-/// Merges remote order into current order. It prioritizes remote order.
-/// A pending order can only be upgraded to active by `remote_order`.
-fn merge_remote_order(current_order: OrderType, remote_order: OrderType) -> OrderType {
-
-    match current_order {
-        OrderType::Pending => {
-            if remote_order == OrderType::Active {
-                return OrderType::Active;
-            } else {
-                return OrderType::Pending;
-            }
-        }
-        _ => remote_order,
+    fn moving_to_2(id: usize) -> ElevatorInfo {
+        let info = step(&ElevatorInfo::idle_at(id, 1), Event::OnNewOrder { btn: UP_AT_2 }).0;
+        assert_eq!((info.state, info.dirn), (State::Moving, DIRN_UP));
+        return info;
     }
-}
 
-/// Creates new list with values of a remote order list, but preserves pending orders in the local list
-///
-/// *`local_order_info` - The local knowledge of the orderlist of a remote elevator
-/// *`remote_orders` - The update received from the remote elevator
-fn merge_remote_orders(local_order_info: OrderList, remote_orders: OrderList) -> OrderList {
+    #[test]
+    fn order_passed_by_a_full_car_is_lost() {
+        let config = config();
+        let mut info = ConnectedElevatorInfo::new(ElevatorInfo::new(&config), &config);
+        let before = moving_to_2(2);
+        assert!(info.update_remote_elevator_info(vec![before.clone()]).is_empty());
 
-    let mut new_order_list: OrderList = OrderList::new(ELEV_NUM_FLOORS);
+        let full = step(&before, Event::OnLoad { percent: 100 }).0;
+        let (after, _) = step(&full, Event::OnFloorArrival { floor: 2 });
+        assert_eq!(info.update_remote_elevator_info(vec![after]), vec![(2, UP_AT_2)]);
+    }
 
-    if ELEV_NUM_FLOORS as usize != remote_orders.up_queue.len() {
-        panic!("Tried to merge elevator orders of different lengths :(");
+    #[test]
+    fn order_served_by_a_car_that_filled_up_is_not_lost() {
+        let config = config();
+        let mut info = ConnectedElevatorInfo::new(ElevatorInfo::new(&config), &config);
+        let (door_open, _) = step(&moving_to_2(2), Event::OnFloorArrival { floor: 2 });
+        info.update_remote_elevator_info(vec![door_open.clone()]);
+        let full = step(&door_open, Event::OnLoad { percent: 100 }).0;
+        let (closed, _) = step(&full, Event::OnDoorTimeOut);
+        assert!(info.update_remote_elevator_info(vec![closed]).is_empty());
     }
 
-    for i in 0..ELEV_NUM_FLOORS as usize {
-        new_order_list.up_queue[i] =
-            merge_remote_order(local_order_info.clone().up_queue[i], remote_orders.clone().up_queue[i]);
-        new_order_list.down_queue[i] = merge_remote_order(
-            local_order_info.clone().down_queue[i],
-            remote_orders.clone().down_queue[i],
-        );
-        new_order_list.inside_queue[i] = merge_remote_order(
-            local_order_info.clone().inside_queue[i],
-            remote_orders.clone().inside_queue[i],
-        );
+    #[test]
+    fn served_only_when_cleared_as_the_door_closes() {
+        let config = config();
+        let mut info = ConnectedElevatorInfo::new(moving_to_2(1), &config);
+        info.hall_orders.press(UP_AT_2);
+        let (door_open, _) = step(&info.get_local_elevator_info().unwrap(), Event::OnFloorArrival { floor: 2 });
+        info.update_local_elevator_info(door_open.clone());
+        assert!(!info.get_hall_orders().is_served(UP_AT_2));
+
+        // The stop button only puts it back to pending
+        let (stopped, _) = step(&door_open, Event::OnStopButton { pressed: true });
+        info.update_local_elevator_info(stopped.clone());
+        assert!(!info.get_hall_orders().is_served(UP_AT_2));
+        let (released, _) = step(&stopped, Event::OnStopButton { pressed: false });
+        info.update_local_elevator_info(released.clone());
+
+        let (closed, _) = step(&released, Event::OnDoorTimeOut);
+        info.update_local_elevator_info(closed);
+        assert!(info.get_hall_orders().is_served(UP_AT_2));
     }
-    return new_order_list;
-}
\ No newline at end of file
+}
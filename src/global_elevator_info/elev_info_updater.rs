@@ -1,26 +1,97 @@
 use crossbeam_channel as cbc;
 use serde;
+use std::fs;
+use std::io;
+use std::path::Path;
 use std::time;
 use std::thread::*;
 use std::collections::HashMap;
 
-use crate::util::constants as setting;
+use crate::util::config::Config;
 use crate::local_elevator::fsm::elevatorfsm::ElevatorInfo;
+use crate::global_elevator_info::hall_orders::HallOrders;
+use crate::global_elevator_info::failure_detector::{FailureDetector, PeerHealth};
+
+/// What is actually broadcast, so the receivers can tell old packets from new ones.
+/// * `incarnation` is picked when the node starts, so a restarted node gets a higher one, see `new_incarnation`
+/// * `seq` counts the packets sent by this incarnation
+/// * `hall_orders` the sender's copy of the hall orders of the building
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct InfoPacket<T> {
+    pub incarnation: u64,
+    pub seq: u64,
+    pub info: T,
+    pub hall_orders: HallOrders,
+}
+
+/// What we learn from the other nodes
+/// * `Peers` the newest info of every node we are connected to, sent when it changes
+/// * `HallOrders` the copy of the hall orders of one of them, sent for every packet
+/// * `Health` the health of every node that is not lost, sent when it changes
+#[derive(Clone, Debug)]
+pub enum RemoteUpdate {
+    Peers(Vec<ElevatorInfo>),
+    HallOrders(HallOrders),
+    Health(HashMap<usize, PeerHealth>),
+}
+
+/// Where in the packet stream of a node we are
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct PacketId {
+    incarnation: u64,
+    seq: u64,
+}
+
+impl<T> InfoPacket<T> {
+    fn id(&self) -> PacketId {
+        return PacketId { incarnation: self.incarnation, seq: self.seq };
+    }
+}
 
+/// Picked once when the node starts, and shared by the transmitter and the receiver. It is taken from the clock,
+/// but is always above the one of the previous run, which is saved to `elevator-<id>.incarnation` in `state_dir`,
+/// so the other nodes do not throw away our packets as old ones if the clock was set back while we were down.
+pub fn new_incarnation(config: &Config) -> io::Result<u64> {
+    let since_epoch = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap_or_default();
+    let mut incarnation = since_epoch.as_nanos() as u64;
+    if config.state_dir.is_empty() {
+        return Ok(incarnation);
+    }
+    let path = Path::new(&config.state_dir).join(format!("elevator-{}.incarnation", config.id()));
+    match fs::read_to_string(&path) {
+        Ok(contents) => {
+            let previous: u64 = contents.trim().parse()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+            incarnation = incarnation.max(previous.saturating_add(1));
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {},
+        Err(err) => return Err(err),
+    }
+    let tmp_path = path.with_extension("incarnation.tmp");
+    fs::write(&tmp_path, incarnation.to_string())?;
+    fs::rename(&tmp_path, &path)?;
+    return Ok(incarnation);
+}
 
 ///Transmitter local ElevatorInfo to network
 pub fn local_elev_info_tx<ElevatorInfo: 'static + Clone + serde::Serialize + std::marker::Send>(
-    elev_info: cbc::Receiver::<ElevatorInfo>){
+    elev_info: cbc::Receiver::<ElevatorInfo>,
+    hall_orders_rx: cbc::Receiver::<HallOrders>,
+    incarnation: u64,
+    config: &Config){
 
-    let (send_bcast_tx, send_bcast_rx) = cbc::unbounded::<ElevatorInfo>();
+    let (send_bcast_tx, send_bcast_rx) = cbc::unbounded::<InfoPacket<ElevatorInfo>>();
     {
+    let port = config.elev_info_port;
     spawn(move || {
-        crate::network_interface::bcast::tx(setting::ELEV_INFO_PORT, send_bcast_rx, 3);
+        crate::network_interface::bcast::tx(port, send_bcast_rx, 3);
     });
     }
 
-    let ticker = cbc::tick(time::Duration::from_millis(setting::INFO_TRANSMIT_PERIOD_MILLISEC));
+    let ticker = cbc::tick(config.info_transmit_period());
     let mut local_info: ElevatorInfo;
+    let mut hall_orders = HallOrders::new(config.num_floors);
+    let mut seq: u64 = 0;
 
     cbc::select! {
         recv(elev_info) -> new_info => {
@@ -31,91 +102,143 @@ pub fn local_elev_info_tx<ElevatorInfo: 'static + Clone + serde::Serialize + std
     loop {
         cbc::select! {
             recv(ticker) -> _ => {
-                    send_bcast_tx.send(local_info.clone()).unwrap();
+                    seq += 1;
+                    send_bcast_tx.send(InfoPacket { incarnation, seq, info: local_info.clone(), hall_orders: hall_orders.clone() }).unwrap();
             },
             recv(elev_info) -> new_info => {
                 local_info = new_info.unwrap();
+            },
+            recv(hall_orders_rx) -> new_hall_orders => {
+                hall_orders = new_hall_orders.unwrap();
             }
         }
     }
 }
 
-///Reciver of other nodes local ElevatorInfo
+///Reciver of other nodes local ElevatorInfo.
+///Packets older than the newest one seen from a node are thrown away. When a node is lost is decided by
+///`FailureDetector`, from how regularly its packets have been arriving. A node that comes back with
+///a new incarnation has restarted, and is reported as lost before it is added again,
+///so its old hall orders are taken over like when it disappears.
+///
+///Packets with our own id but another incarnation than `incarnation` come from another live node using
+///our id, and the incarnation of that node is sent on `id_conflict`. Right after the start they may also
+///be the last packets of our previous run, so they are only reported after `time_until_peer_lost_millisec`.
 pub fn remote_elev_info_rx<T: serde::de::DeserializeOwned>(
-    elev_info_update: cbc::Sender::<Vec<ElevatorInfo>>,
-    cab_backup_channel: cbc::Sender::<ElevatorInfo>){
+    elev_info_update: cbc::Sender::<RemoteUpdate>,
+    id_conflict: cbc::Sender::<u64>,
+    incarnation: u64,
+    config: &Config){
 
-    let timeout = time::Duration::from_millis(setting::TIME_UNTIL_PEER_LOST_MILLISEC);
+    let timeout = config.peer_lost_timeout();
+    let started = time::Instant::now();
     
-    let (elev_info_recv_tx, elev_info_recv_rx) = cbc::unbounded::<ElevatorInfo>();
+    let (elev_info_recv_tx, elev_info_recv_rx) = cbc::unbounded::<InfoPacket<ElevatorInfo>>();
+    let port = config.elev_info_port;
     spawn(move || {
-        crate::network_interface::bcast::rx(setting::ELEV_INFO_PORT, elev_info_recv_tx);
+        crate::network_interface::bcast::rx(port, elev_info_recv_tx);
     });
 
-    let mut last_seen: HashMap<usize, time::Instant> = HashMap::new();
+    let mut detector = FailureDetector::new(config);
     let mut active_peers: HashMap<usize, ElevatorInfo> = HashMap::new();
-    let mut lost_peers: HashMap<usize, ElevatorInfo> = HashMap::new();
+    // Forgotten when a node is lost, so it is heard again even if it comes back with a lower incarnation,
+    // e.g. without a state_dir after its clock was set back
+    let mut newest_packet: HashMap<usize, PacketId> = HashMap::new();
 
     loop {
         let mut modified = false;
-        let mut reconnected_elevator = false;
-        let mut lost_peers_temp = Vec::new();
-
-        let r = elev_info_recv_rx.recv_timeout(timeout);
+        let mut health_changed = false;
+        let mut remote_hall_orders = None;
+
+        // Woken at least as often as the packets should arrive, so silence is noticed
+        let r = elev_info_recv_rx.recv_timeout(config.info_transmit_period()).ok();
+        if let Some(packet) = r.as_ref() {
+            if packet.info.id == config.id() && packet.incarnation != incarnation && started.elapsed() > timeout {
+                id_conflict.send(packet.incarnation).unwrap();
+            }
+        }
+        // Duplicated and reordered packets are thrown away
+        let r = r
+            .filter(|packet| newest_packet.get(&packet.info.id).is_none_or(|newest| packet.id() > *newest));
         let now = time::Instant::now();
 
-        match r {
-            Ok(val) => {
-                let elev_info = val.clone();
-                let id = elev_info.clone().id;
-                if !last_seen.contains_key(&id.clone()) {
-                    modified = true;
-                    reconnected_elevator = true;
-                }
-                
-                // Send cab calls to reconnecting node
-                if reconnected_elevator {
-                    if lost_peers.contains_key(&id.clone()){
-                        cab_backup_channel.send(lost_peers.get(&id).unwrap().clone()).unwrap();
-                    }
-                } 
-
-                match active_peers.get(&id).cloned() {
-                    Some(existing_info) => {
-                        if existing_info != elev_info.clone() {
-                            modified = true;
-                        }
-                    }
-                    None => {}
-                }
-
-                last_seen.insert(id.clone(), now);
-                active_peers.insert(id.clone(), elev_info.clone());
-                lost_peers.remove(&id.clone());
+        if let Some(packet) = r {
+            let packet_id = packet.id();
+            let elev_info = packet.info;
+            if elev_info.id != config.id() {
+                remote_hall_orders = Some(packet.hall_orders);
             }
-            Err(_) => {},
-        }   
-
-        // Finding lost peers
-        for (id, when_last_seen) in &last_seen {
-            if now.duration_since(*when_last_seen) > timeout {
-                lost_peers_temp.push(active_peers.get(id).unwrap().clone());
-                lost_peers.insert(id.clone(), active_peers.get(&id).unwrap().clone());
+            let id = elev_info.id;
+            let newest = newest_packet.insert(id, packet_id);
+
+            let restarted = newest.is_some_and(|newest| packet_id.incarnation > newest.incarnation);
+            if restarted && active_peers.remove(&id).is_some() {
+                println!("Elevator {} has restarted", id);
+                detector.remove(id);
+                let peers: Vec<ElevatorInfo> = active_peers.values().cloned().collect();
+                elev_info_update.send(RemoteUpdate::Peers(peers)).unwrap();
+            }
+
+            if let Some(health) = detector.heartbeat(id, now) {
+                println!("Elevator {} is {:?}", id, health);
+                health_changed = true;
+            }
+            if active_peers.get(&id) != Some(&elev_info) {
                 modified = true;
             }
+            active_peers.insert(id, elev_info);
         }
 
-        // .. and removing them
-        for elev in &lost_peers_temp {
-            last_seen.remove(&elev.id.clone());
-            active_peers.remove(&elev.id.clone());
+        // Only lost peers are removed, a suspected one keeps its orders until it is lost or trusted again
+        for (id, health) in detector.update(now) {
+            println!("Elevator {} is {:?}", id, health);
+            health_changed = true;
+            if health == PeerHealth::Lost {
+                active_peers.remove(&id);
+                newest_packet.remove(&id);
+                modified = true;
+            }
         }
 
         // Sending remote elevator update
         if modified {
-            let peers: Vec<ElevatorInfo>;
-            peers = active_peers.values().cloned().collect();
-            elev_info_update.send(peers.clone()).unwrap();
+            let peers: Vec<ElevatorInfo> = active_peers.values().cloned().collect();
+            elev_info_update.send(RemoteUpdate::Peers(peers)).unwrap();
+        }
+        if health_changed {
+            elev_info_update.send(RemoteUpdate::Health(detector.health())).unwrap();
         }
+        if let Some(hall_orders) = remote_hall_orders {
+            elev_info_update.send(RemoteUpdate::HallOrders(hall_orders)).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_in(state_dir: &Path) -> Config {
+        return Config { id: Some(4), state_dir: state_dir.to_string_lossy().into_owned(), ..Default::default() };
+    }
+
+    #[test]
+    fn incarnation_goes_up_even_if_the_clock_went_back() {
+        let dir = std::env::temp_dir().join(format!("incarnation-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = config_in(&dir);
+
+        let first = new_incarnation(&config).unwrap();
+        assert!(new_incarnation(&config).unwrap() > first);
+
+        // The previous run had a clock far ahead of ours
+        let ahead = u64::MAX / 2;
+        fs::write(dir.join("elevator-4.incarnation"), ahead.to_string()).unwrap();
+        assert_eq!(new_incarnation(&config).unwrap(), ahead + 1);
+        assert_eq!(new_incarnation(&config).unwrap(), ahead + 2);
+
+        fs::write(dir.join("elevator-4.incarnation"), "garbage").unwrap();
+        assert_eq!(new_incarnation(&config).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
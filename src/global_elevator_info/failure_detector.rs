@@ -0,0 +1,243 @@
+//! Decides when a peer is gone, from how regularly its elevator info has been arriving.
+//!
+//! This is a phi accrual failure detector. For every peer we keep the time between the last packets, and from
+//! their mean and standard deviation we compute `phi`, how unlikely it is that the peer is still alive given how
+//! long it has been silent. `phi` = 1 means a 10 % chance of being wrong, `phi` = 2 means 1 %, and so on.
+//! A peer with steady packets is suspected soon after they stop, while a peer with a lot of jitter gets more time.
+//!
+//! ```text
+//! Connected -> Suspect -> Lost
+//!     ^           |
+//!     +-----------+  after RECOVERY_HEARTBEATS packets in a row
+//! ```
+//!
+//! * `Connected` - the packets arrive as expected
+//! * `Suspect` - `phi` passed `phi_suspect`. The peer keeps its orders, but gets no new ones if anyone else can take them.
+//! * `Lost` - `phi` passed `phi_lost`, and the peer has been silent for at least `time_until_peer_lost_millisec`.
+//!   Only now are its hall orders handed over, so a short burst of lost packets does not move any orders.
+use serde;
+use std::collections::{HashMap, VecDeque};
+use std::time;
+
+use crate::util::config::Config;
+
+/// How many of the latest times between packets the estimate is based on
+const WINDOW_SIZE: usize = 100;
+/// How many packets in a row a suspected peer must send before it is trusted again
+const RECOVERY_HEARTBEATS: u32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerHealth {
+    Connected,
+    Suspect,
+    Lost,
+}
+
+/// What we know about the packets from one peer
+/// * `intervals` the latest times between packets, in milliseconds
+/// * `recovering` the packets received in a row since the peer was suspected
+struct PeerDetector {
+    intervals: VecDeque<f64>,
+    last_arrival: time::Instant,
+    health: PeerHealth,
+    recovering: u32,
+}
+
+impl PeerDetector {
+    fn new(now: time::Instant, expected_interval: f64) -> PeerDetector {
+        PeerDetector {
+            intervals: VecDeque::from([expected_interval]),
+            last_arrival: now,
+            health: PeerHealth::Connected,
+            recovering: 0,
+        }
+    }
+
+    fn heartbeat(&mut self, now: time::Instant) {
+        if self.intervals.len() == WINDOW_SIZE {
+            self.intervals.pop_front();
+        }
+        self.intervals.push_back(now.duration_since(self.last_arrival).as_secs_f64() * 1000.0);
+        self.last_arrival = now;
+        if self.health == PeerHealth::Suspect {
+            self.recovering += 1;
+            if self.recovering >= RECOVERY_HEARTBEATS {
+                self.health = PeerHealth::Connected;
+                self.recovering = 0;
+            }
+        }
+    }
+
+    /// The suspicion level after `silence` without packets
+    fn phi(&self, silence: time::Duration, min_std_dev: f64) -> f64 {
+        let n = self.intervals.len() as f64;
+        let mean = self.intervals.iter().sum::<f64>() / n;
+        let variance = self.intervals.iter().map(|i| (i - mean) * (i - mean)).sum::<f64>() / n;
+        let std_dev = variance.sqrt().max(min_std_dev);
+
+        // A logistic approximation of the tail of the normal distribution
+        let elapsed = silence.as_secs_f64() * 1000.0;
+        let y = (elapsed - mean) / std_dev;
+        let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+        if elapsed > mean {
+            return -(e / (1.0 + e)).log10();
+        }
+        return -(1.0 - 1.0 / (1.0 + e)).log10();
+    }
+}
+
+/// Keeps the health of every peer we have heard from
+pub struct FailureDetector {
+    peers: HashMap<usize, PeerDetector>,
+    phi_suspect: f64,
+    phi_lost: f64,
+    min_std_dev: f64,
+    min_silence_before_lost: time::Duration,
+    /// Until we have measured anything, a peer is expected to send as often as we do
+    expected_interval: f64,
+}
+
+impl FailureDetector {
+    pub fn new(config: &Config) -> FailureDetector {
+        FailureDetector {
+            peers: HashMap::new(),
+            phi_suspect: config.phi_suspect,
+            phi_lost: config.phi_lost,
+            min_std_dev: config.heartbeat_min_std_dev_millisec as f64,
+            min_silence_before_lost: config.peer_lost_timeout(),
+            expected_interval: config.info_transmit_period().as_secs_f64() * 1000.0,
+        }
+    }
+
+    /// A packet arrived from `id`. Returns the new health of the peer if it changed, also when it is new.
+    pub fn heartbeat(&mut self, id: usize, now: time::Instant) -> Option<PeerHealth> {
+        match self.peers.get_mut(&id) {
+            Some(peer) => {
+                let before = peer.health;
+                peer.heartbeat(now);
+                return Some(peer.health).filter(|&health| health != before);
+            }
+            None => {
+                self.peers.insert(id, PeerDetector::new(now, self.expected_interval));
+                return Some(PeerHealth::Connected);
+            }
+        }
+    }
+
+    /// Checks every peer against the clock. Returns the peers whose health changed.
+    /// Lost peers are forgotten, and start over as new ones if they come back.
+    pub fn update(&mut self, now: time::Instant) -> Vec<(usize, PeerHealth)> {
+        let mut changes = Vec::new();
+        for (&id, peer) in self.peers.iter_mut() {
+            let silence = now.saturating_duration_since(peer.last_arrival);
+            let phi = peer.phi(silence, self.min_std_dev);
+            if phi >= self.phi_suspect {
+                peer.recovering = 0;
+            }
+            let health = if phi >= self.phi_lost && silence >= self.min_silence_before_lost {
+                PeerHealth::Lost
+            } else if phi >= self.phi_suspect || peer.health == PeerHealth::Suspect {
+                PeerHealth::Suspect
+            } else {
+                PeerHealth::Connected
+            };
+            if health != peer.health {
+                peer.health = health;
+                changes.push((id, health));
+            }
+        }
+        self.peers.retain(|_, peer| peer.health != PeerHealth::Lost);
+        changes.sort_by_key(|&(id, _)| id);
+        return changes;
+    }
+
+    pub fn remove(&mut self, id: usize) {
+        self.peers.remove(&id);
+    }
+
+    /// The health of every peer that is not lost
+    pub fn health(&self) -> HashMap<usize, PeerHealth> {
+        return self.peers.iter().map(|(&id, peer)| (id, peer.health)).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    const PEER: usize = 7;
+
+    /// A detector that has heard from `PEER` after each of `intervals`, in milliseconds.
+    /// Returns it with the time of the last packet.
+    fn after_heartbeats(intervals: impl IntoIterator<Item = u64>) -> (FailureDetector, Instant) {
+        let mut detector = FailureDetector::new(&Config::default());
+        let mut now = Instant::now();
+        detector.heartbeat(PEER, now);
+        for interval in intervals {
+            now += Duration::from_millis(interval);
+            detector.heartbeat(PEER, now);
+            assert!(detector.update(now).is_empty());
+        }
+        return (detector, now);
+    }
+
+    fn steady() -> (FailureDetector, Instant) {
+        return after_heartbeats(std::iter::repeat_n(15, 200));
+    }
+
+    /// Every other packet is late by almost half a second
+    fn jittery() -> (FailureDetector, Instant) {
+        return after_heartbeats([15, 400].iter().copied().cycle().take(200));
+    }
+
+    fn phi(detector: &FailureDetector, silence: u64) -> f64 {
+        return detector.peers[&PEER].phi(Duration::from_millis(silence), detector.min_std_dev);
+    }
+
+    #[test]
+    fn steady_heartbeats_keep_phi_low() {
+        let (detector, _) = steady();
+        assert!(phi(&detector, 15) < 1.0);
+        assert!(phi(&detector, 30) < detector.phi_suspect);
+        assert_eq!(detector.health()[&PEER], PeerHealth::Connected);
+    }
+
+    #[test]
+    fn long_silence_is_lost() {
+        let (mut detector, last) = steady();
+        assert_eq!(detector.update(last + Duration::from_millis(200)), vec![(PEER, PeerHealth::Suspect)]);
+        assert_eq!(detector.update(last + Duration::from_millis(800)), vec![(PEER, PeerHealth::Lost)]);
+        assert!(!detector.health().contains_key(&PEER));
+    }
+
+    #[test]
+    fn jittery_link_is_suspected_but_not_lost() {
+        let (mut detector, last) = jittery();
+        assert!(detector.update(last + Duration::from_millis(400)).is_empty());
+        assert_eq!(detector.update(last + Duration::from_millis(800)), vec![(PEER, PeerHealth::Suspect)]);
+        assert!(phi(&detector, 800) < detector.phi_lost);
+        assert_eq!(detector.health()[&PEER], PeerHealth::Suspect);
+    }
+
+    #[test]
+    fn suspect_recovers_after_heartbeats_in_a_row() {
+        let (mut detector, mut now) = steady();
+        now += Duration::from_millis(200);
+        assert_eq!(detector.update(now), vec![(PEER, PeerHealth::Suspect)]);
+        for beat in 1..=RECOVERY_HEARTBEATS {
+            now += Duration::from_millis(15);
+            let expected = if beat == RECOVERY_HEARTBEATS { Some(PeerHealth::Connected) } else { None };
+            assert_eq!(detector.heartbeat(PEER, now), expected);
+        }
+    }
+
+    #[test]
+    fn short_silence_is_not_lost_even_with_high_phi() {
+        let (mut detector, last) = steady();
+        detector.update(last + Duration::from_millis(400));
+        assert!(phi(&detector, 400) >= detector.phi_lost);
+        assert_eq!(detector.health()[&PEER], PeerHealth::Suspect);
+    }
+}
@@ -0,0 +1,285 @@
+//! The hall orders of the whole building, as agreed on by all the connected elevators.
+//!
+//! Every node keeps its own copy of the table and broadcasts it with its elevator info. The copies are
+//! merged with `HallOrders::merge`, so they all end up the same. Each order goes through the lifecycle
+//!
+//! ```text
+//! Unknown -> New -> Confirmed -> Served -> New -> ...
+//! ```
+//!
+//! * `Unknown` - we have just started and have not heard from anyone yet
+//! * `New` - someone pressed the button. Every node that sees it adds itself to `acks`.
+//! * `Confirmed` - every connected node has seen the order. Only now is the light turned on.
+//! * `Served` - the elevator that served it opened the door, and the light goes off everywhere
+//!
+//! Every change of state counts up the `seq` of the order. When merging, the copy with the highest `seq` wins,
+//! so an old copy of the table can not undo a change, not even one from an earlier round of the cycle.
+//! `Unknown` has `seq` 0 and gives way to anything. If two nodes changed the order at the same time, the
+//! state furthest along in the list above wins.
+//!
+//! A node that has just started presses an `Unknown` order from `seq` 0, while the others may have served it
+//! many times already, so the press would lose to their `Served`. The press is therefore kept in mind until we
+//! hear a copy that knows as much as ours, and made again on top of it if that copy has the order served.
+use serde;
+
+use crate::local_elevator::elevio::poll::CallButton;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Lifecycle {
+    Unknown,
+    New,
+    Confirmed,
+    Served,
+}
+
+/// A single hall order
+/// * `seq` how many times the state has changed
+/// * `acks` the ids of the nodes that have seen it as `New`, in order
+/// * `unheard_press` we pressed it while it was `Unknown`, and have not yet heard how far the others have come
+#[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct HallOrder {
+    pub state: Lifecycle,
+    pub seq: u64,
+    pub acks: Vec<usize>,
+    #[serde(skip)]
+    pub unheard_press: bool,
+}
+
+impl HallOrder {
+    fn unknown() -> HallOrder {
+        HallOrder { state: Lifecycle::Unknown, seq: 0, acks: Vec::new(), unheard_press: false }
+    }
+
+    /// Moves on to `state`, forgetting the acks of the one before
+    fn change_to(&mut self, state: Lifecycle) {
+        *self = HallOrder { state, seq: self.seq + 1, acks: Vec::new(), unheard_press: false };
+    }
+
+    fn merge(&mut self, remote: &HallOrder) {
+        let (seq, unheard_press) = (self.seq, self.unheard_press);
+        if (remote.seq, remote.state) == (self.seq, self.state) {
+            for &id in remote.acks.iter() {
+                self.ack(id);
+            }
+        } else if (remote.seq, remote.state) > (self.seq, self.state) {
+            *self = remote.clone();
+        }
+        if unheard_press && remote.seq >= seq {
+            self.unheard_press = false;
+            // The others may have served it before we started, but not the press we made since
+            if self.state == Lifecycle::Served {
+                self.change_to(Lifecycle::New);
+            }
+        } else {
+            self.unheard_press = unheard_press;
+        }
+    }
+
+    fn ack(&mut self, id: usize) {
+        if let Err(pos) = self.acks.binary_search(&id) {
+            self.acks.insert(pos, id);
+        }
+    }
+}
+
+/// This node's copy of the hall orders, indexed by floor
+#[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct HallOrders {
+    pub up: Vec<HallOrder>,
+    pub down: Vec<HallOrder>,
+}
+
+impl HallOrders {
+    pub fn new(num_floors: u8) -> HallOrders {
+        HallOrders {
+            up: vec![HallOrder::unknown(); usize::from(num_floors)],
+            down: vec![HallOrder::unknown(); usize::from(num_floors)],
+        }
+    }
+
+    /// The button was pressed. Nothing happens if the order is already there.
+    pub fn press(&mut self, button: CallButton) {
+        let order = self.get_mut(button);
+        if matches!(order.state, Lifecycle::Unknown | Lifecycle::Served) {
+            let unheard_press = order.state == Lifecycle::Unknown;
+            order.change_to(Lifecycle::New);
+            order.unheard_press = unheard_press;
+        }
+    }
+
+    /// The order was served, or cancelled, and the light can go off
+    pub fn serve(&mut self, button: CallButton) {
+        let order = self.get_mut(button);
+        if order.state != Lifecycle::Served {
+            order.change_to(Lifecycle::Served);
+        }
+    }
+
+    /// Takes in the copy of another node
+    pub fn merge(&mut self, remote: &HallOrders) {
+        if remote.up.len() != self.up.len() {
+            println!("Ignoring hall orders for {} floors", remote.up.len());
+            return;
+        }
+        for (local, remote) in self.up.iter_mut().zip(remote.up.iter()).chain(self.down.iter_mut().zip(remote.down.iter())) {
+            local.merge(remote);
+        }
+    }
+
+    /// Acknowledges the new orders as `local_id`, and confirms those that every one of `connected_ids` has seen.
+    /// Returns true if anything changed.
+    pub fn acknowledge(&mut self, local_id: usize, connected_ids: &[usize]) -> bool {
+        let before = self.clone();
+        for order in self.up.iter_mut().chain(self.down.iter_mut()) {
+            if order.state == Lifecycle::New {
+                order.ack(local_id);
+                if connected_ids.iter().all(|id| order.acks.binary_search(id).is_ok()) {
+                    order.change_to(Lifecycle::Confirmed);
+                }
+            }
+        }
+        return *self != before;
+    }
+
+    pub fn is_confirmed(&self, button: CallButton) -> bool {
+        return self.get(button).state == Lifecycle::Confirmed;
+    }
+
+    pub fn is_served(&self, button: CallButton) -> bool {
+        return self.get(button).state == Lifecycle::Served;
+    }
+
+    pub fn get(&self, button: CallButton) -> &HallOrder {
+        match button.call {
+            0 => &self.up[usize::from(button.floor)],
+            1 => &self.down[usize::from(button.floor)],
+            _ => unreachable!(),
+        }
+    }
+
+    fn get_mut(&mut self, button: CallButton) -> &mut HallOrder {
+        match button.call {
+            0 => &mut self.up[usize::from(button.floor)],
+            1 => &mut self.down[usize::from(button.floor)],
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UP_AT_1: CallButton = CallButton { floor: 1, call: 0 };
+
+    #[test]
+    fn confirmed_when_every_connected_node_has_acked() {
+        let mut orders = HallOrders::new(4);
+        orders.press(UP_AT_1);
+        orders.acknowledge(1, &[1, 2]);
+        assert_eq!(orders.get(UP_AT_1).state, Lifecycle::New);
+
+        let mut remote = HallOrders::new(4);
+        remote.merge(&orders);
+        remote.acknowledge(2, &[1, 2]);
+        assert!(remote.is_confirmed(UP_AT_1));
+
+        orders.merge(&remote);
+        assert!(orders.is_confirmed(UP_AT_1));
+    }
+
+    #[test]
+    fn acks_of_the_same_round_are_combined() {
+        let mut a = HallOrders::new(4);
+        a.press(UP_AT_1);
+        let mut b = a.clone();
+        a.acknowledge(1, &[1, 2, 3]);
+        b.acknowledge(2, &[1, 2, 3]);
+        a.merge(&b);
+        assert_eq!(a.get(UP_AT_1).acks, vec![1, 2]);
+        assert_eq!(a.get(UP_AT_1).state, Lifecycle::New);
+    }
+
+    #[test]
+    fn old_copy_does_not_undo_a_serve() {
+        let mut orders = HallOrders::new(4);
+        orders.press(UP_AT_1);
+        orders.acknowledge(1, &[1]);
+        let old_copy = orders.clone();
+        orders.serve(UP_AT_1);
+        orders.merge(&old_copy);
+        assert!(orders.is_served(UP_AT_1));
+    }
+
+    #[test]
+    fn stale_confirmed_does_not_override_a_new_press() {
+        let mut orders = HallOrders::new(4);
+        orders.press(UP_AT_1);
+        orders.acknowledge(1, &[1]);
+        let stale = orders.clone();
+        orders.serve(UP_AT_1);
+        orders.press(UP_AT_1);
+        assert_eq!(orders.get(UP_AT_1).state, Lifecycle::New);
+
+        orders.merge(&stale);
+        assert_eq!(orders.get(UP_AT_1).state, Lifecycle::New);
+        let mut behind = stale.clone();
+        behind.merge(&orders);
+        assert_eq!(behind.get(UP_AT_1).state, Lifecycle::New);
+    }
+
+    #[test]
+    fn unknown_gives_way_to_anything() {
+        let mut orders = HallOrders::new(4);
+        orders.press(UP_AT_1);
+        orders.serve(UP_AT_1);
+        let mut restarted = HallOrders::new(4);
+        restarted.merge(&orders);
+        assert!(restarted.is_served(UP_AT_1));
+    }
+
+    #[test]
+    fn press_right_after_a_restart_is_not_lost() {
+        let mut cluster = HallOrders::new(4);
+        for _ in 0..3 {
+            cluster.press(UP_AT_1);
+            cluster.acknowledge(1, &[1]);
+            cluster.serve(UP_AT_1);
+        }
+
+        let mut restarted = HallOrders::new(4);
+        restarted.press(UP_AT_1);
+        // Another node that has just started does not know any better
+        restarted.merge(&HallOrders::new(4));
+        restarted.merge(&cluster);
+        assert_eq!(restarted.get(UP_AT_1).state, Lifecycle::New);
+        assert!(restarted.get(UP_AT_1).seq > cluster.get(UP_AT_1).seq);
+
+        cluster.merge(&restarted);
+        cluster.acknowledge(1, &[1, 2]);
+        restarted.merge(&cluster);
+        restarted.acknowledge(2, &[1, 2]);
+        assert!(restarted.is_confirmed(UP_AT_1));
+
+        // Only that one press is made again, a later serve sticks
+        restarted.serve(UP_AT_1);
+        restarted.merge(&cluster);
+        assert!(restarted.is_served(UP_AT_1));
+    }
+
+    #[test]
+    fn press_right_after_a_restart_joins_an_order_already_there() {
+        let mut cluster = HallOrders::new(4);
+        cluster.press(UP_AT_1);
+        cluster.acknowledge(1, &[1]);
+        cluster.serve(UP_AT_1);
+        cluster.press(UP_AT_1);
+        cluster.acknowledge(1, &[1]);
+
+        let mut restarted = HallOrders::new(4);
+        restarted.press(UP_AT_1);
+        restarted.merge(&cluster);
+        assert_eq!(restarted.get(UP_AT_1), cluster.get(UP_AT_1));
+    }
+}
@@ -0,0 +1,78 @@
+//! How a node gets its id when none is given in the configuration.
+//!
+//! The node first listens to the elevator info on `elev_info_port` for twice `time_until_peer_lost_millisec`,
+//! so it has heard from every live node, and then picks a random id none of them use. The id is saved to
+//! `node-<hw_server>.id` in `state_dir`, so the car connected to the same hardware keeps its id, and its
+//! saved orders, when it is restarted.
+//!
+//! Two nodes can still end up with the same id, if they claim at the same time or are configured with it.
+//! That is caught by `elev_info_updater::remote_elev_info_rx`, which reports another incarnation using our id.
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::util::config::Config;
+use crate::local_elevator::fsm::elevatorfsm::ElevatorInfo;
+use crate::global_elevator_info::elev_info_updater::InfoPacket;
+
+/// Returns the id of this node: the configured one, the one saved on an earlier run, or a newly claimed one
+pub fn claim(config: &Config) -> io::Result<usize> {
+    if let Some(id) = config.id {
+        return Ok(id);
+    }
+    let path = path(config);
+    if let Some(path) = path.as_ref() {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                return contents.trim().parse()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)));
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {},
+            Err(err) => return Err(err),
+        }
+    }
+
+    println!("No id given, listening for the other elevators before claiming one");
+    let live_ids = discover(config);
+    let id = loop {
+        let candidate = random_id();
+        if !live_ids.contains(&candidate) {
+            break candidate;
+        }
+    };
+    if let Some(path) = path.as_ref() {
+        save(path, id)?;
+    }
+    return Ok(id);
+}
+
+/// Where the claimed id is kept, or `None` if nothing is saved
+fn path(config: &Config) -> Option<PathBuf> {
+    if config.state_dir.is_empty() {
+        return None;
+    }
+    let car: String = config.hw_server.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+        .collect();
+    return Some(Path::new(&config.state_dir).join(format!("node-{}.id", car)));
+}
+
+/// The ids of the nodes that broadcast their elevator info right now
+fn discover(config: &Config) -> HashSet<usize> {
+    let packets = crate::network_interface::bcast::rx_for::<InfoPacket<ElevatorInfo>>(
+        config.elev_info_port, 2 * config.peer_lost_timeout());
+    return packets.iter().map(|packet| packet.info.id).collect();
+}
+
+/// Small enough to read in the logs, and large enough that two nodes claiming at once do not pick the same
+fn random_id() -> usize {
+    return rand::random::<usize>() % 1_000_000;
+}
+
+fn save(path: &Path, id: usize) -> io::Result<()> {
+    let tmp_path = path.with_extension("id.tmp");
+    fs::write(&tmp_path, id.to_string())?;
+    fs::rename(&tmp_path, path)?;
+    return Ok(());
+}
@@ -1,6 +1,11 @@
+#![allow(clippy::needless_return)]
+
 pub mod global_elevator_info {
     pub mod connected_elevators;
     pub mod elev_info_updater;
+    pub mod failure_detector;
+    pub mod hall_orders;
+    pub mod node_id;
 }
 
 pub mod network_interface {
@@ -10,20 +15,36 @@ pub mod network_interface {
 pub mod local_elevator {
     pub mod elevio {
         pub mod elev;
+        pub mod mock;
         pub mod poll;
     }
     pub mod fsm {
         pub mod door_timer;
         pub mod elevatorfsm;
         pub mod order_list;
+        pub mod state_file;
     }
 }
 
 pub mod order_assigner {
+    pub mod cab_backup;
+    pub mod cost_function;
+    pub mod hall_request_assigner;
+    pub mod order_protocol;
     pub mod order_receiver;
     pub mod order_transmitter;
+    pub mod parking;
+    pub mod takeover;
+}
+
+pub mod simulator {
+    pub mod physics;
+    pub mod server;
 }
 
 pub mod util {
+    pub mod config;
+    pub mod console;
     pub mod constants;
-}
\ No newline at end of file
+    pub mod timer;
+}
@@ -1,21 +1,42 @@
 //#![allow(dead_code)]
 
-use std::net::TcpStream;
+use std::net::{TcpStream, ToSocketAddrs};
 use std::sync::*;
 use std::fmt;
-use std::io::*;
+use std::io::{self, Read, Write};
+use std::thread::{sleep, spawn};
+use std::time;
+use crossbeam_channel as cbc;
 
 pub const DIRN_DOWN:    u8 = u8::MAX;
 pub const DIRN_STOP:    u8 = 0;
 pub const DIRN_UP:      u8 = 1;
 
+const IO_TIMEOUT_MILLISEC: u64 = 500;
+const MIN_RECONNECT_BACKOFF_MILLISEC: u64 = 100;
+const MAX_RECONNECT_BACKOFF_MILLISEC: u64 = 5000;
+
 #[derive(Clone, Debug)]
 pub struct ElevatorHW {
-        socket:     Arc<Mutex<TcpStream>>,
+        addr:       String,
+        link:       Arc<Mutex<Link>>,
+        link_lost:  cbc::Sender<()>,
     pub num_floors: u8,
 }
 
-#[derive(PartialEq, Debug)]
+/// The connection to the hardware server, and the last output state we asked for.
+/// The output state is re-issued whenever the connection is re-established.
+#[derive(Debug)]
+struct Link {
+    stream:             Option<TcpStream>,
+    motor_dirn:         u8,
+    call_button_lights: Vec<[bool; 3]>,
+    floor_indicator:    Option<u8>,
+    door_light:         bool,
+    stop_light:         bool,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum HardwareCommand{
     DoorLight{on: bool},
     MotorDirection{dirn: u8},
@@ -24,106 +45,269 @@ pub enum HardwareCommand{
     FloorLight{floor: u8}
 }
 
+/// Whether we are currently able to talk to the elevator hardware
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum HardwareStatus {
+    Available,
+    Unavailable,
+}
+
+#[derive(Debug)]
+pub enum HardwareError {
+    /// The link to the hardware is down and is being re-established
+    Disconnected,
+    /// The reply did not answer the request that was sent
+    Framing{sent: u8, received: u8},
+    Io(io::Error),
+}
+
+/// Everything the rest of the system needs from the elevator hardware.
+///
+/// `ElevatorHW` talks to the hardware server over TCP, while `mock::MockElevatorIo`
+/// is an in-memory stand-in that can be scripted from tests.
+pub trait ElevatorIo: Send {
+    fn num_floors(&self) -> u8;
+
+    fn motor_direction(&self, dirn: u8) -> Result<(), HardwareError>;
+    fn call_button_light(&self, floor: u8, call: u8, on: bool) -> Result<(), HardwareError>;
+    fn floor_indicator(&self, floor: u8) -> Result<(), HardwareError>;
+    fn door_light(&self, on: bool) -> Result<(), HardwareError>;
+    fn stop_button_light(&self, on: bool) -> Result<(), HardwareError>;
+
+    fn call_button(&self, floor: u8, call: u8) -> Result<bool, HardwareError>;
+    fn floor_sensor(&self) -> Result<Option<u8>, HardwareError>;
+    fn stop_button(&self) -> Result<bool, HardwareError>;
+    fn obstruction(&self) -> Result<bool, HardwareError>;
+    /// The load of the car in percent of its capacity.
+    /// This is an extension of the hardware protocol, so only poll it if the hardware server has a load sensor.
+    fn load(&self) -> Result<u8, HardwareError>;
+
+    /// Helper function so elev can interact with the hardwarecommand struct
+    fn execute_command(&self, command: HardwareCommand) -> Result<(), HardwareError> {
+        match command {
+            HardwareCommand::CallButtonLight{floor, call, on} => self.call_button_light(floor, call, on),
+            HardwareCommand::DoorLight{on} => self.door_light(on),
+            HardwareCommand::FloorLight{floor} => self.floor_indicator(floor),
+            HardwareCommand::MotorDirection{dirn} => self.motor_direction(dirn),
+            HardwareCommand::StopLight{on} => self.stop_button_light(on)
+        }
+    }
+}
+
 
 impl ElevatorHW {
 
-    pub fn init(addr: &str, num_floors: u8) -> Result<ElevatorHW> {
+    /// Connects to the hardware server at `addr`.
+    ///
+    /// If the connection is lost later on, it is re-established in the background with exponential backoff.
+    /// `HardwareStatus::Unavailable` is sent on `status_tx` when the link drops, and `HardwareStatus::Available`
+    /// once it is back up and the last known motor and light state has been re-issued.
+    pub fn init(addr: &str, num_floors: u8, status_tx: cbc::Sender<HardwareStatus>) -> io::Result<ElevatorHW> {
+        let stream = connect(addr)?;
+        let (link_lost_tx, link_lost_rx) = cbc::unbounded::<()>();
+        let link = Arc::new(Mutex::new(Link {
+            stream: Some(stream),
+            motor_dirn: DIRN_STOP,
+            call_button_lights: vec![[false; 3]; num_floors.into()],
+            floor_indicator: None,
+            door_light: false,
+            stop_light: false,
+        }));
+        {
+            let link = link.clone();
+            let addr = addr.to_string();
+            spawn(move || reconnect(addr, link, link_lost_rx, status_tx));
+        }
         Ok(Self {
-            socket: Arc::new(Mutex::new( TcpStream::connect(addr)? )),
-            num_floors: num_floors,
+            addr: addr.to_string(),
+            link,
+            link_lost: link_lost_tx,
+            num_floors,
         })
     }
+
+    /// Records the new output state and sends `buf` if the link is up
+    fn set_output<F: FnOnce(&mut Link)>(&self, buf: [u8; 4], update: F) -> Result<(), HardwareError> {
+        let mut link = self.link.lock().unwrap();
+        update(&mut link);
+        let res = match link.stream.as_mut() {
+            Some(sock) => sock.write_all(&buf).map_err(HardwareError::Io),
+            None => return Err(HardwareError::Disconnected),
+        };
+        if res.is_err() {
+            self.drop_link(&mut link);
+        }
+        return res;
+    }
+
+    /// Sends `buf` and waits for the four byte reply
+    fn request(&self, mut buf: [u8; 4]) -> Result<[u8; 4], HardwareError> {
+        let mut link = self.link.lock().unwrap();
+        let opcode = buf[0];
+        let res = match link.stream.as_mut() {
+            Some(sock) => sock.write_all(&buf)
+                .and_then(|_| sock.read_exact(&mut buf))
+                .map_err(HardwareError::Io),
+            None => return Err(HardwareError::Disconnected),
+        };
+        let res = res.and_then(|_| {
+            if buf[0] != opcode {
+                return Err(HardwareError::Framing{sent: opcode, received: buf[0]});
+            }
+            return Ok(buf);
+        });
+        if res.is_err() {
+            self.drop_link(&mut link);
+        }
+        return res;
+    }
+
+    fn drop_link(&self, link: &mut Link) {
+        if link.stream.take().is_some() {
+            self.link_lost.send(()).unwrap();
+        }
+    }
+}
+
+impl ElevatorIo for ElevatorHW {
+
+    fn num_floors(&self) -> u8 {
+        return self.num_floors;
+    }
     
-    
-    pub fn motor_direction(&self, dirn: u8){
-        let buf = [1, dirn, 0, 0];
-        let mut sock = self.socket.lock().unwrap();
-        sock.write(&buf).unwrap();
+    fn motor_direction(&self, dirn: u8) -> Result<(), HardwareError> {
+        return self.set_output([1, dirn, 0, 0], |link| link.motor_dirn = dirn);
     }
     
-    pub fn call_button_light(&self, floor: u8, call: u8, on: bool){
-        let buf = [2, call, floor, on as u8];
-        let mut sock = self.socket.lock().unwrap();
-        sock.write(&buf).unwrap();
+    fn call_button_light(&self, floor: u8, call: u8, on: bool) -> Result<(), HardwareError> {
+        return self.set_output([2, call, floor, on as u8], |link| {
+            if let Some(light) = link.call_button_lights.get_mut(usize::from(floor))
+                .and_then(|lights| lights.get_mut(usize::from(call))) {
+                *light = on;
+            }
+        });
     }
     
-    pub fn floor_indicator(&self, floor: u8){
-        let buf = [3, floor, 0, 0];
-        let mut sock = self.socket.lock().unwrap();
-        sock.write(&buf).unwrap();
+    fn floor_indicator(&self, floor: u8) -> Result<(), HardwareError> {
+        return self.set_output([3, floor, 0, 0], |link| link.floor_indicator = Some(floor));
     }
     
-    pub fn door_light(&self, on: bool){
-        let buf = [4, on as u8, 0, 0];
-        let mut sock = self.socket.lock().unwrap();
-        sock.write(&buf).unwrap();
+    fn door_light(&self, on: bool) -> Result<(), HardwareError> {
+        return self.set_output([4, on as u8, 0, 0], |link| link.door_light = on);
     }
     
-    pub fn stop_button_light(&self, on: bool){
-        let buf = [5, on as u8, 0, 0];
-        let mut sock = self.socket.lock().unwrap();
-        sock.write(&buf).unwrap();
+    fn stop_button_light(&self, on: bool) -> Result<(), HardwareError> {
+        return self.set_output([5, on as u8, 0, 0], |link| link.stop_light = on);
     }
     
     
     
-    pub fn call_button(&self, floor: u8, call: u8) -> bool {
-        let mut buf = [6, call, floor, 0];
-        let mut sock = self.socket.lock().unwrap();
-        sock.write(&mut buf).unwrap();
-        sock.read(&mut buf).unwrap();
-        return buf[1] != 0;
+    fn call_button(&self, floor: u8, call: u8) -> Result<bool, HardwareError> {
+        let buf = self.request([6, call, floor, 0])?;
+        return Ok(buf[1] != 0);
     }
     
-    pub fn floor_sensor(&self) -> Option<u8> {
-        let mut buf = [7, 0, 0, 0];
-        let mut sock = self.socket.lock().unwrap();
-        sock.write(&mut buf).unwrap();
-        sock.read(&mut buf).unwrap();
+    fn floor_sensor(&self) -> Result<Option<u8>, HardwareError> {
+        let buf = self.request([7, 0, 0, 0])?;
         if buf[1] != 0 {
-            Some(buf[2])
+            Ok(Some(buf[2]))
         } else {
-            None
+            Ok(None)
         }
     }
     
-    pub fn stop_button(&self) -> bool {
-        let mut buf = [8, 0, 0, 0];
-        let mut sock = self.socket.lock().unwrap();
-        sock.write(&mut buf).unwrap();
-        sock.read(&mut buf).unwrap();
-        return buf[1] != 0;
+    fn stop_button(&self) -> Result<bool, HardwareError> {
+        let buf = self.request([8, 0, 0, 0])?;
+        return Ok(buf[1] != 0);
     }
     
-    pub fn obstruction(&self) -> bool {
-        let mut buf = [9, 0, 0, 0];
-        let mut sock = self.socket.lock().unwrap();
-        sock.write(&mut buf).unwrap();
-        sock.read(&mut buf).unwrap();
-        return buf[1] != 0;
+    fn obstruction(&self) -> Result<bool, HardwareError> {
+        let buf = self.request([9, 0, 0, 0])?;
+        return Ok(buf[1] != 0);
     }
 
-    /// Helper function so elev can interact with the hardwarecommand struct
-    pub fn execute_command(&self, command: HardwareCommand) {
-        match command {
-            HardwareCommand::CallButtonLight{floor, call, on} => self.call_button_light(floor, call, on),
-            HardwareCommand::DoorLight{on} => self.door_light(on),
-            HardwareCommand::FloorLight{floor} => self.floor_indicator(floor),
-            HardwareCommand::MotorDirection{dirn} => self.motor_direction(dirn),
-            HardwareCommand::StopLight{on} => self.stop_button_light(on)
-        }
+    fn load(&self) -> Result<u8, HardwareError> {
+        let buf = self.request([10, 0, 0, 0])?;
+        return Ok(buf[1]);
     }
 }
 
 impl fmt::Display for ElevatorHW {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let addr = self.socket.lock().unwrap().peer_addr().unwrap();
-        write!(f, "Elevator@{}({})", addr, self.num_floors)
+        write!(f, "Elevator@{}({})", self.addr, self.num_floors)
     }
 }
 
+impl fmt::Display for HardwareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HardwareError::Disconnected => write!(f, "hardware is disconnected"),
+            HardwareError::Framing{sent, received} =>
+                write!(f, "got reply to opcode {} when waiting for reply to opcode {}", received, sent),
+            HardwareError::Io(e) => write!(f, "hardware i/o error: {}", e),
+        }
+    }
+}
 
+impl std::error::Error for HardwareError {}
 
+fn connect(addr: &str) -> io::Result<TcpStream> {
+    let timeout = time::Duration::from_millis(IO_TIMEOUT_MILLISEC);
+    let mut last_err = io::Error::new(io::ErrorKind::NotFound, format!("could not resolve {}", addr));
+    for sock_addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&sock_addr, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                stream.set_nodelay(true)?;
+                return Ok(stream);
+            }
+            Err(e) => last_err = e,
+        }
+    }
+    return Err(last_err);
+}
 
+/// Re-issues the output state recorded in `link` on a fresh connection
+fn restore_outputs(stream: &mut TcpStream, link: &Link) -> io::Result<()> {
+    stream.write_all(&[1, link.motor_dirn, 0, 0])?;
+    stream.write_all(&[4, link.door_light as u8, 0, 0])?;
+    stream.write_all(&[5, link.stop_light as u8, 0, 0])?;
+    if let Some(floor) = link.floor_indicator {
+        stream.write_all(&[3, floor, 0, 0])?;
+    }
+    for (floor, lights) in link.call_button_lights.iter().enumerate() {
+        for (call, on) in lights.iter().enumerate() {
+            stream.write_all(&[2, call as u8, floor as u8, *on as u8])?;
+        }
+    }
+    return Ok(());
+}
 
+fn reconnect(
+    addr: String,
+    link: Arc<Mutex<Link>>,
+    link_lost_rx: cbc::Receiver<()>,
+    status_tx: cbc::Sender<HardwareStatus>) {
 
+    let min_backoff = time::Duration::from_millis(MIN_RECONNECT_BACKOFF_MILLISEC);
+    let max_backoff = time::Duration::from_millis(MAX_RECONNECT_BACKOFF_MILLISEC);
+    while link_lost_rx.recv().is_ok() {
+        println!("Lost connection to elevator hardware at {}, reconnecting", addr);
+        status_tx.send(HardwareStatus::Unavailable).unwrap();
+        let mut backoff = min_backoff;
+        loop {
+            sleep(backoff);
+            if let Ok(mut stream) = connect(&addr) {
+                let mut link = link.lock().unwrap();
+                if restore_outputs(&mut stream, &link).is_ok() {
+                    link.stream = Some(stream);
+                    break;
+                }
+            }
+            backoff = std::cmp::min(backoff * 2, max_backoff);
+        }
+        println!("Reconnected to elevator hardware at {}", addr);
+        status_tx.send(HardwareStatus::Available).unwrap();
+    }
+}
@@ -0,0 +1,190 @@
+//! In-memory elevator hardware for driving the pollers and the fsm without a hardware server.
+use std::sync::*;
+
+use super::elev::{ElevatorIo, HardwareCommand, HardwareError};
+
+#[derive(Clone, Debug)]
+struct MockState {
+    call_buttons: Vec<[bool; 3]>,
+    floor_sensor: Option<u8>,
+    stop_button: bool,
+    obstruction: bool,
+    load: u8,
+    connected: bool,
+    commands: Vec<HardwareCommand>,
+}
+
+/// Scriptable stand-in for `ElevatorHW`.
+///
+/// Every output (motor, lights) is recorded as a `HardwareCommand`, and every input
+/// returns whatever was last preset. While disconnected, every call fails with
+/// `HardwareError::Disconnected` and nothing is recorded. Clones share the same state, so one clone can be
+/// handed to a poller while the test scripts inputs and inspects commands through another.
+///
+/// # Example
+/// ```rust
+/// use elevator::local_elevator::elevio::elev::{ElevatorIo, HardwareCommand, DIRN_UP};
+/// use elevator::local_elevator::elevio::mock::MockElevatorIo;
+/// let mock = MockElevatorIo::new(4);
+/// mock.set_floor_sensor(Some(2));
+/// assert_eq!(mock.floor_sensor().unwrap(), Some(2));
+/// mock.execute_command(HardwareCommand::MotorDirection{dirn: DIRN_UP}).unwrap();
+/// assert_eq!(mock.commands(), vec![HardwareCommand::MotorDirection{dirn: DIRN_UP}]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct MockElevatorIo {
+    num_floors: u8,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockElevatorIo {
+    pub fn new(num_floors: u8) -> MockElevatorIo {
+        MockElevatorIo {
+            num_floors,
+            state: Arc::new(Mutex::new(MockState {
+                call_buttons: vec![[false; 3]; num_floors.into()],
+                floor_sensor: None,
+                stop_button: false,
+                obstruction: false,
+                load: 0,
+                connected: true,
+                commands: Vec::new(),
+            })),
+        }
+    }
+
+    pub fn set_call_button(&self, floor: u8, call: u8, pressed: bool) {
+        self.state.lock().unwrap().call_buttons[usize::from(floor)][usize::from(call)] = pressed;
+    }
+
+    pub fn set_floor_sensor(&self, floor: Option<u8>) {
+        self.state.lock().unwrap().floor_sensor = floor;
+    }
+
+    pub fn set_stop_button(&self, pressed: bool) {
+        self.state.lock().unwrap().stop_button = pressed;
+    }
+
+    pub fn set_obstruction(&self, active: bool) {
+        self.state.lock().unwrap().obstruction = active;
+    }
+
+    pub fn set_load(&self, percent: u8) {
+        self.state.lock().unwrap().load = percent;
+    }
+
+    /// Simulates losing or regaining the link to the hardware
+    pub fn set_connected(&self, connected: bool) {
+        self.state.lock().unwrap().connected = connected;
+    }
+
+    /// All commands received so far, oldest first.
+    pub fn commands(&self) -> Vec<HardwareCommand> {
+        return self.state.lock().unwrap().commands.clone();
+    }
+
+    /// Returns all commands received so far and forgets them.
+    pub fn take_commands(&self) -> Vec<HardwareCommand> {
+        return std::mem::take(&mut self.state.lock().unwrap().commands);
+    }
+
+    fn record(&self, command: HardwareCommand) -> Result<(), HardwareError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.connected {
+            return Err(HardwareError::Disconnected);
+        }
+        state.commands.push(command);
+        return Ok(());
+    }
+
+    fn read<T, F: FnOnce(&MockState) -> T>(&self, f: F) -> Result<T, HardwareError> {
+        let state = self.state.lock().unwrap();
+        if !state.connected {
+            return Err(HardwareError::Disconnected);
+        }
+        return Ok(f(&state));
+    }
+}
+
+impl ElevatorIo for MockElevatorIo {
+    fn num_floors(&self) -> u8 {
+        return self.num_floors;
+    }
+
+    fn motor_direction(&self, dirn: u8) -> Result<(), HardwareError> {
+        return self.record(HardwareCommand::MotorDirection{dirn});
+    }
+
+    fn call_button_light(&self, floor: u8, call: u8, on: bool) -> Result<(), HardwareError> {
+        return self.record(HardwareCommand::CallButtonLight{floor, call, on});
+    }
+
+    fn floor_indicator(&self, floor: u8) -> Result<(), HardwareError> {
+        return self.record(HardwareCommand::FloorLight{floor});
+    }
+
+    fn door_light(&self, on: bool) -> Result<(), HardwareError> {
+        return self.record(HardwareCommand::DoorLight{on});
+    }
+
+    fn stop_button_light(&self, on: bool) -> Result<(), HardwareError> {
+        return self.record(HardwareCommand::StopLight{on});
+    }
+
+    fn call_button(&self, floor: u8, call: u8) -> Result<bool, HardwareError> {
+        return self.read(|s| s.call_buttons[usize::from(floor)][usize::from(call)]);
+    }
+
+    fn floor_sensor(&self) -> Result<Option<u8>, HardwareError> {
+        return self.read(|s| s.floor_sensor);
+    }
+
+    fn stop_button(&self) -> Result<bool, HardwareError> {
+        return self.read(|s| s.stop_button);
+    }
+
+    fn obstruction(&self) -> Result<bool, HardwareError> {
+        return self.read(|s| s.obstruction);
+    }
+
+    fn load(&self) -> Result<u8, HardwareError> {
+        return self.read(|s| s.load);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local_elevator::elevio::elev::{DIRN_DOWN, DIRN_STOP};
+
+    #[test]
+    fn every_command_is_recorded_through_execute_command() {
+        let mock = MockElevatorIo::new(4);
+        let commands = [
+            HardwareCommand::MotorDirection { dirn: DIRN_DOWN },
+            HardwareCommand::CallButtonLight { floor: 3, call: 1, on: true },
+            HardwareCommand::FloorLight { floor: 2 },
+            HardwareCommand::DoorLight { on: true },
+            HardwareCommand::StopLight { on: false },
+        ];
+        for command in commands.iter() {
+            mock.execute_command(*command).unwrap();
+        }
+        assert_eq!(mock.take_commands(), commands.to_vec());
+        assert!(mock.commands().is_empty());
+    }
+
+    #[test]
+    fn nothing_is_recorded_while_disconnected() {
+        let mock = MockElevatorIo::new(4);
+        mock.set_connected(false);
+        assert!(matches!(mock.execute_command(HardwareCommand::MotorDirection { dirn: DIRN_STOP }),
+            Err(HardwareError::Disconnected)));
+        assert!(matches!(mock.floor_sensor(), Err(HardwareError::Disconnected)));
+        assert!(mock.commands().is_empty());
+
+        mock.set_connected(true);
+        mock.execute_command(HardwareCommand::MotorDirection { dirn: DIRN_STOP }).unwrap();
+        assert_eq!(mock.commands(), vec![HardwareCommand::MotorDirection { dirn: DIRN_STOP }]);
+    }
+}
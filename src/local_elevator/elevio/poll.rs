@@ -2,74 +2,369 @@ use std::time;
 use std::thread;
 use crossbeam_channel as cbc;
 use serde;
-use super::elev;
+use super::elev::ElevatorIo;
+use crate::util::config::Config;
 
 pub const HALL_UP:      u8 = 0;
 pub const HALL_DOWN:    u8 = 1;
 pub const CAB:          u8 = 2;
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Copy, Clone)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct CallButton {
     pub floor:  u8,
     pub call:   u8,
 }
 
+/// Everything the poller can tell us about the hardware inputs
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum HardwareEvent {
+    /// A call button was pressed
+    CallButton(CallButton),
+    /// The car arrived at a floor. Reported every time the car comes onto the sensor, also when it turned
+    /// back to the floor it just left.
+    FloorSensor(u8),
+    StopButton(bool),
+    Obstruction(bool),
+    /// The load of the car changed, in percent of its capacity
+    Load(u8),
+}
 
-pub fn call_buttons(elev: elev::ElevatorHW, ch: cbc::Sender<CallButton>, period: time::Duration){
+/// How often an input is read, and how long a new value must be stable before it is reported.
+/// A `debounce` of zero reports every change immediately.
+#[derive(Debug, Copy, Clone)]
+pub struct InputSchedule {
+    pub period:     time::Duration,
+    pub debounce:   time::Duration,
+}
 
-    let mut prev = vec![[false; 3]; elev.num_floors.into()];
-    loop {
-        for f in 0..elev.num_floors {
-            for c in 0..3 {
-                let v = elev.call_button(f, c);
-                if v  &&  prev[f as usize][c as usize] != v {
-                    ch.send(CallButton{floor: f, call: c}).unwrap();
-                }
-                prev[f as usize][c as usize] = v;
-            }
+#[derive(Debug, Copy, Clone)]
+pub struct PollSettings {
+    pub call_buttons:   InputSchedule,
+    pub floor_sensor:   InputSchedule,
+    pub stop_button:    InputSchedule,
+    pub obstruction:    InputSchedule,
+    /// `None` if the car has no load sensor
+    pub load:           Option<InputSchedule>,
+}
+
+impl PollSettings {
+    pub fn from_config(config: &Config) -> PollSettings {
+        let schedule = |period: u64, debounce: u64| InputSchedule {
+            period: time::Duration::from_millis(period),
+            debounce: time::Duration::from_millis(debounce),
+        };
+        PollSettings {
+            call_buttons: schedule(config.poll_call_buttons_millisec, config.debounce_call_buttons_millisec),
+            floor_sensor: schedule(config.poll_floor_sensor_millisec, config.debounce_floor_sensor_millisec),
+            stop_button: schedule(config.poll_stop_button_millisec, config.debounce_stop_button_millisec),
+            obstruction: schedule(config.poll_obstruction_millisec, config.debounce_obstruction_millisec),
+            load: if config.load_sensor {
+                Some(schedule(config.poll_load_millisec, config.debounce_load_millisec))
+            } else {
+                None
+            },
         }
-        thread::sleep(period)
     }
 }
 
-pub fn floor_sensor(elev: elev::ElevatorHW, ch: cbc::Sender<u8>, period: time::Duration){
-    
-    let mut prev = u8::MAX;
-    loop {
-        match elev.floor_sensor() {
-            Some(f) => 
-                if f != prev {
-                    ch.send(f).unwrap();
-                    prev = f;
+/// Keeps track of the last reported value of an input, and of a new value waiting to become stable
+#[derive(Debug, Copy, Clone)]
+struct Debounced<T: Copy + PartialEq> {
+    reported:   T,
+    candidate:  T,
+    since:      time::Instant,
+}
+
+impl<T: Copy + PartialEq> Debounced<T> {
+    fn new(initial: T, now: time::Instant) -> Debounced<T> {
+        Debounced {
+            reported: initial,
+            candidate: initial,
+            since: now,
+        }
+    }
+
+    /// Returns the new value if it differs from the reported one and has been stable for `debounce`
+    fn update(&mut self, value: T, now: time::Instant, debounce: time::Duration) -> Option<T> {
+        if value != self.candidate {
+            self.candidate = value;
+            self.since = now;
+        }
+        if self.candidate != self.reported && now.duration_since(self.since) >= debounce {
+            self.reported = self.candidate;
+            return Some(self.reported);
+        }
+        return None;
+    }
+
+    fn reset(&mut self, value: T, now: time::Instant) {
+        self.reported = value;
+        self.candidate = value;
+        self.since = now;
+    }
+}
+
+/// The inputs as last read, and when each of them is read next
+struct Poller<E: ElevatorIo> {
+    elev:               E,
+    settings:           PollSettings,
+    next_call_buttons:  time::Instant,
+    next_floor_sensor:  time::Instant,
+    next_stop_button:   time::Instant,
+    next_obstruction:   time::Instant,
+    next_load:          time::Instant,
+    call_buttons:       Vec<[Debounced<bool>; 3]>,
+    floor_sensor:       Debounced<Option<u8>>,
+    stop_button:        Debounced<bool>,
+    obstruction:        Debounced<bool>,
+    load:               Debounced<u8>,
+}
+
+impl<E: ElevatorIo> Poller<E> {
+    fn new(elev: E, settings: PollSettings, now: time::Instant) -> Poller<E> {
+        let num_floors = elev.num_floors().into();
+        Poller {
+            elev,
+            settings,
+            next_call_buttons: now,
+            next_floor_sensor: now,
+            next_stop_button: now,
+            next_obstruction: now,
+            next_load: now,
+            call_buttons: vec![[Debounced::new(false, now); 3]; num_floors],
+            floor_sensor: Debounced::new(None, now),
+            stop_button: Debounced::new(false, now),
+            obstruction: Debounced::new(false, now),
+            load: Debounced::new(0, now),
+        }
+    }
+
+    /// Reads the inputs that are due at `now`. Returns the changes, and when the next input is due.
+    fn poll(&mut self, now: time::Instant) -> (Vec<HardwareEvent>, time::Instant) {
+        let mut events = Vec::new();
+        let elev = &self.elev;
+        let settings = &self.settings;
+
+        if now >= self.next_call_buttons {
+            self.next_call_buttons = now + settings.call_buttons.period;
+            'scan: for f in 0..elev.num_floors() {
+                for c in 0..3 {
+                    let v = match elev.call_button(f, c) {
+                        Ok(v) => v,
+                        Err(_) => break 'scan,
+                    };
+                    let button = &mut self.call_buttons[usize::from(f)][usize::from(c)];
+                    if let Some(true) = button.update(v, now, settings.call_buttons.debounce) {
+                        events.push(HardwareEvent::CallButton(CallButton{floor: f, call: c}));
+                    }
+                }
+            }
+        }
+
+        if now >= self.next_floor_sensor {
+            self.next_floor_sensor = now + settings.floor_sensor.period;
+            match elev.floor_sensor() {
+                Ok(v) => {
+                    if let Some(Some(f)) = self.floor_sensor.update(v, now, settings.floor_sensor.debounce) {
+                        events.push(HardwareEvent::FloorSensor(f));
+                    }
+                },
+                // Report the floor again once the hardware is back, the fsm needs it to recover
+                Err(_) => self.floor_sensor.reset(None, now),
+            }
+        }
+
+        if now >= self.next_stop_button {
+            self.next_stop_button = now + settings.stop_button.period;
+            match elev.stop_button() {
+                Ok(v) => {
+                    if let Some(v) = self.stop_button.update(v, now, settings.stop_button.debounce) {
+                        events.push(HardwareEvent::StopButton(v));
+                    }
                 },
-            None => (),
+                // Report the switch again if it is still active once the hardware is back
+                Err(_) => self.stop_button.reset(false, now),
+            }
         }
-        thread::sleep(period)
+
+        if now >= self.next_obstruction {
+            self.next_obstruction = now + settings.obstruction.period;
+            match elev.obstruction() {
+                Ok(v) => {
+                    if let Some(v) = self.obstruction.update(v, now, settings.obstruction.debounce) {
+                        events.push(HardwareEvent::Obstruction(v));
+                    }
+                },
+                // Report the switch again if it is still active once the hardware is back
+                Err(_) => self.obstruction.reset(false, now),
+            }
+        }
+
+        if let Some(schedule) = settings.load {
+            if now >= self.next_load {
+                self.next_load = now + schedule.period;
+                match elev.load() {
+                    Ok(v) => {
+                        if let Some(v) = self.load.update(v, now, schedule.debounce) {
+                            events.push(HardwareEvent::Load(v));
+                        }
+                    },
+                    // Report the load again once the hardware is back
+                    Err(_) => self.load.reset(0, now),
+                }
+            }
+        }
+
+        let mut next = *[self.next_call_buttons, self.next_floor_sensor, self.next_stop_button, self.next_obstruction]
+            .iter().min().unwrap();
+        if settings.load.is_some() {
+            next = next.min(self.next_load);
+        }
+        return (events, next);
     }
 }
 
-pub fn stop_button(elev: elev::ElevatorHW, ch: cbc::Sender<bool>, period: time::Duration){
-    
-    let mut prev = false;
+/// Polls all hardware inputs from a single thread, each at its own rate, and sends every change on `ch`.
+pub fn run<E: ElevatorIo>(elev: E, ch: cbc::Sender<HardwareEvent>, settings: PollSettings) {
+    let mut poller = Poller::new(elev, settings, time::Instant::now());
     loop {
-        let v = elev.stop_button();
-        if prev != v {
-            ch.send(v).unwrap();
-            prev = v;
+        let (events, next) = poller.poll(time::Instant::now());
+        for event in events {
+            ch.send(event).unwrap();
+        }
+        let now = time::Instant::now();
+        if next > now {
+            thread::sleep(next - now);
         }
-        thread::sleep(period)
     }
 }
 
-pub fn obstruction(elev: elev::ElevatorHW, ch: cbc::Sender<bool>, period: time::Duration){
-    
-    let mut prev = false;
-    loop {
-        let v = elev.obstruction();
-        if prev != v {
-            ch.send(v).unwrap();
-            prev = v;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local_elevator::elevio::mock::MockElevatorIo;
+
+    const PERIOD: time::Duration = time::Duration::from_millis(10);
+
+    /// Every input read every `PERIOD`, and reported once it has been stable for `debounce`
+    fn settings(debounce: time::Duration) -> PollSettings {
+        let schedule = InputSchedule { period: PERIOD, debounce };
+        return PollSettings {
+            call_buttons: schedule,
+            floor_sensor: schedule,
+            stop_button: schedule,
+            obstruction: schedule,
+            load: Some(schedule),
+        };
+    }
+
+    /// A poller on the mock, with the time of its first round
+    fn poller(mock: &MockElevatorIo, debounce: time::Duration) -> (Poller<MockElevatorIo>, time::Instant) {
+        let start = time::Instant::now();
+        return (Poller::new(mock.clone(), settings(debounce), start), start);
+    }
+
+    #[test]
+    fn reports_every_change_once() {
+        let mock = MockElevatorIo::new(4);
+        let (mut poller, start) = poller(&mock, time::Duration::ZERO);
+        assert_eq!(poller.poll(start), (vec![], start + PERIOD));
+
+        mock.set_call_button(1, HALL_DOWN, true);
+        mock.set_floor_sensor(Some(2));
+        mock.set_stop_button(true);
+        mock.set_obstruction(true);
+        mock.set_load(40);
+        let (events, _) = poller.poll(start + PERIOD);
+        assert_eq!(events, vec![
+            HardwareEvent::CallButton(CallButton { floor: 1, call: HALL_DOWN }),
+            HardwareEvent::FloorSensor(2),
+            HardwareEvent::StopButton(true),
+            HardwareEvent::Obstruction(true),
+            HardwareEvent::Load(40),
+        ]);
+        assert!(poller.poll(start + PERIOD * 2).0.is_empty());
+
+        // Only presses are reported for the call buttons, both edges for the switches
+        mock.set_call_button(1, HALL_DOWN, false);
+        mock.set_stop_button(false);
+        assert_eq!(poller.poll(start + PERIOD * 3).0, vec![HardwareEvent::StopButton(false)]);
+    }
+
+    #[test]
+    fn inputs_are_only_read_when_due() {
+        let mock = MockElevatorIo::new(4);
+        let (mut poller, start) = poller(&mock, time::Duration::ZERO);
+        poller.poll(start);
+        mock.set_stop_button(true);
+        assert!(poller.poll(start + PERIOD / 2).0.is_empty());
+        assert_eq!(poller.poll(start + PERIOD).0, vec![HardwareEvent::StopButton(true)]);
+    }
+
+    #[test]
+    fn held_inputs_are_reported_again_after_a_disconnect() {
+        let mock = MockElevatorIo::new(4);
+        let (mut poller, start) = poller(&mock, time::Duration::ZERO);
+        mock.set_floor_sensor(Some(0));
+        mock.set_stop_button(true);
+        assert_eq!(poller.poll(start).0, vec![HardwareEvent::FloorSensor(0), HardwareEvent::StopButton(true)]);
+
+        mock.set_connected(false);
+        assert!(poller.poll(start + PERIOD).0.is_empty());
+        mock.set_connected(true);
+        assert_eq!(poller.poll(start + PERIOD * 2).0, vec![HardwareEvent::FloorSensor(0), HardwareEvent::StopButton(true)]);
+    }
+
+    #[test]
+    fn glitch_shorter_than_the_debounce_is_ignored() {
+        let mock = MockElevatorIo::new(4);
+        let (mut poller, start) = poller(&mock, PERIOD * 3);
+        poller.poll(start);
+        mock.set_obstruction(true);
+        mock.set_call_button(2, CAB, true);
+        assert!(poller.poll(start + PERIOD).0.is_empty());
+        assert!(poller.poll(start + PERIOD * 2).0.is_empty());
+        mock.set_obstruction(false);
+        mock.set_call_button(2, CAB, false);
+        for round in 3..10 {
+            assert!(poller.poll(start + PERIOD * round).0.is_empty());
+        }
+    }
+
+    #[test]
+    fn level_held_for_the_debounce_is_reported_once() {
+        let mock = MockElevatorIo::new(4);
+        let (mut poller, start) = poller(&mock, PERIOD * 3);
+        poller.poll(start);
+        mock.set_obstruction(true);
+        mock.set_call_button(2, CAB, true);
+        for round in 1..4 {
+            assert!(poller.poll(start + PERIOD * round).0.is_empty());
         }
-        thread::sleep(period)
+        assert_eq!(poller.poll(start + PERIOD * 4).0, vec![
+            HardwareEvent::CallButton(CallButton { floor: 2, call: CAB }),
+            HardwareEvent::Obstruction(true),
+        ]);
+        assert!(poller.poll(start + PERIOD * 5).0.is_empty());
+
+        // Releasing is debounced the same way
+        mock.set_obstruction(false);
+        assert!(poller.poll(start + PERIOD * 6).0.is_empty());
+        assert_eq!(poller.poll(start + PERIOD * 9).0, vec![HardwareEvent::Obstruction(false)]);
+    }
+
+    #[test]
+    fn floor_is_reported_again_after_leaving_the_sensor() {
+        let mock = MockElevatorIo::new(4);
+        let (mut poller, start) = poller(&mock, time::Duration::ZERO);
+        mock.set_floor_sensor(Some(1));
+        assert_eq!(poller.poll(start).0, vec![HardwareEvent::FloorSensor(1)]);
+
+        // The car leaves floor 1, and is sent back to it before it reaches the next one
+        mock.set_floor_sensor(None);
+        assert!(poller.poll(start + PERIOD).0.is_empty());
+        mock.set_floor_sensor(Some(1));
+        assert_eq!(poller.poll(start + PERIOD * 2).0, vec![HardwareEvent::FloorSensor(1)]);
+        assert!(poller.poll(start + PERIOD * 3).0.is_empty());
     }
 }
@@ -1,14 +1,15 @@
 use crate::local_elevator::fsm::order_list::{OrderList, OrderType};
 use crate::local_elevator::elevio::elev::{DIRN_DOWN, DIRN_STOP, DIRN_UP};
-use crate::local_elevator::fsm::elevatorfsm::Elevator;
+use crate::local_elevator::fsm::elevatorfsm::{ClearPolicy, ElevatorInfo};
+use crate::local_elevator::elevio::poll::{CallButton, CAB, HALL_DOWN, HALL_UP};
 
 
-pub fn choose_direction(fsm: &mut Elevator) -> u8 {
-    let dirn = fsm.get_dirn();
-    let order_list = fsm.get_orders();
-    let floor = usize::from(fsm.get_floor());
-    let orders_above: bool = order_above(&order_list, floor);
-    let orders_below: bool = order_below(&order_list, floor);
+pub fn choose_direction(info: &ElevatorInfo) -> u8 {
+    let dirn = info.dirn;
+    let order_list = &info.responsible_orders;
+    let floor = usize::from(info.floor);
+    let orders_above: bool = order_above(order_list, floor);
+    let orders_below: bool = order_below(order_list, floor);
     match dirn {
         DIRN_UP => {
             if orders_above {
@@ -37,33 +38,95 @@ pub fn choose_direction(fsm: &mut Elevator) -> u8 {
     }
 }
 
-pub fn should_stop(fsm: &mut Elevator) -> bool {
-    let dirn = fsm.get_dirn();
-    let order_list = fsm.get_orders();
-    let floor = usize::from(fsm.get_floor());
+/// A full car only stops for cab orders, or when there is nothing more to do in its direction
+pub fn should_stop(info: &ElevatorInfo) -> bool {
+    let dirn = info.dirn;
+    let order_list = &info.responsible_orders;
+    let floor = usize::from(info.floor);
+    let takes_hall_calls = !info.is_full();
     match dirn {
         DIRN_DOWN => {
             return {
-                order_list.down_queue[floor] == OrderType::Active ||
+                (takes_hall_calls && order_list.down_queue[floor] == OrderType::Active) ||
                 order_list.inside_queue[floor] == OrderType::Active ||
-                !order_below(&order_list, floor)
+                !order_below(order_list, floor)
             }
         }
         DIRN_UP => {
             return {
-                order_list.up_queue[floor] == OrderType::Active ||
+                (takes_hall_calls && order_list.up_queue[floor] == OrderType::Active) ||
                 order_list.inside_queue[floor] == OrderType::Active ||
-                !order_above(&order_list, floor)
+                !order_above(order_list, floor)
             }
         }
         _ => true,
     }
 }
 
+/// The direction we leave `info.floor` in, when the door opens there.
+/// We keep going the way we came as long as someone here or further on wants to go that way.
+pub fn announce_direction(info: &ElevatorInfo) -> u8 {
+    let order_list = &info.responsible_orders;
+    let floor = usize::from(info.floor);
+    let up_here = order_list.up_queue[floor] == OrderType::Active;
+    let down_here = order_list.down_queue[floor] == OrderType::Active;
+    match info.dirn {
+        DIRN_UP if up_here || order_above(order_list, floor) => DIRN_UP,
+        DIRN_DOWN if down_here || order_below(order_list, floor) => DIRN_DOWN,
+        _ if up_here => DIRN_UP,
+        _ if down_here => DIRN_DOWN,
+        _ => choose_direction(info),
+    }
+}
+
+/// Clears the orders served when the door closes at `info.floor`, according to `info.clear_policy`
+pub fn clear_orders_at_floor(info: &mut ElevatorInfo) {
+    let floor = info.floor;
+    let order_list = &mut info.responsible_orders;
+    match (info.clear_policy, info.dirn) {
+        (ClearPolicy::InDirection, DIRN_UP) => {
+            order_list.remove_order(CallButton { floor, call: HALL_UP });
+            order_list.remove_order(CallButton { floor, call: CAB });
+        }
+        (ClearPolicy::InDirection, DIRN_DOWN) => {
+            order_list.remove_order(CallButton { floor, call: HALL_DOWN });
+            order_list.remove_order(CallButton { floor, call: CAB });
+        }
+        _ => order_list.clear_orders_on_floor(floor),
+    }
+}
+
+/// If nobody wants to continue in our direction, but someone at this floor wants to go the other way,
+/// returns the direction we should announce instead of closing the door.
+pub fn turn_around_direction(info: &ElevatorInfo) -> Option<u8> {
+    if info.clear_policy != ClearPolicy::InDirection {
+        return None;
+    }
+    let order_list = &info.responsible_orders;
+    let floor = usize::from(info.floor);
+    match info.dirn {
+        DIRN_UP if !order_above(order_list, floor) && order_list.down_queue[floor] == OrderType::Active => {
+            Some(DIRN_DOWN)
+        }
+        DIRN_DOWN if !order_below(order_list, floor) && order_list.up_queue[floor] == OrderType::Active => {
+            Some(DIRN_UP)
+        }
+        _ => None,
+    }
+}
+
+/// Says if an order at the floor where our door is open is served before we leave
+pub fn served_at_open_door(info: &ElevatorInfo, button: CallButton) -> bool {
+    return matches!(
+        (info.clear_policy, button.call, info.dirn),
+        (ClearPolicy::All, _, _) | (_, CAB, _) | (_, _, DIRN_STOP) | (_, HALL_UP, DIRN_UP) | (_, HALL_DOWN, DIRN_DOWN)
+    );
+}
+
 fn order_below(order_list: &OrderList, floor: usize) -> bool {
-    let up_queue = &(*order_list).up_queue;
-    let down_queue = &(*order_list).down_queue;
-    let inside_queue = &(*order_list).inside_queue;
+    let up_queue = &order_list.up_queue;
+    let down_queue = &order_list.down_queue;
+    let inside_queue = &order_list.inside_queue;
 
     return single_queue_order_below(up_queue, floor)
         || single_queue_order_below(down_queue, floor)
@@ -71,9 +134,9 @@ fn order_below(order_list: &OrderList, floor: usize) -> bool {
 }
 
 fn order_above(order_list: &OrderList, floor: usize) -> bool {
-    let up_queue = &(*order_list).up_queue;
-    let down_queue = &(*order_list).down_queue;
-    let inside_queue = &(*order_list).inside_queue;
+    let up_queue = &order_list.up_queue;
+    let down_queue = &order_list.down_queue;
+    let inside_queue = &order_list.inside_queue;
 
     return single_queue_order_above(up_queue, floor)
         || single_queue_order_above(down_queue, floor)
@@ -7,8 +7,10 @@ use crate::local_elevator::elevio::elev as elevio;
 use crate::local_elevator::elevio::poll;
 use crate::local_elevator::fsm::order_list;
 use crate::local_elevator::fsm::order_list::OrderType;
-use crate::util::constants as setting;
+use crate::local_elevator::fsm::state_file::SavedState;
+use crate::util::config::Config;
 use crate::local_elevator::fsm::door_timer::TimerCommand;
+use crate::util::timer::TimerRequest;
 
 #[path = "./direction_decider.rs"]
 mod direction_decider;
@@ -22,15 +24,71 @@ pub enum State {
     ObstrTimedOut,
     Moving,
     MovTimedOut,
+    /// The stop button is held. `door_open` tells if we stopped at a floor and opened the door,
+    /// `parking` the home floor we were on our way to, if any.
+    EmergencyStop { door_open: bool, parking: Option<u8> },
+    /// Idle, but on the way to our home floor `floor`. A new order ends the trip right away.
+    Parking { floor: u8 },
 }
 
-#[derive(Debug)]
+/// Which orders are served when the door opens at a floor.
+/// * `All` - every order on the floor, no matter which way the passengers want to go
+/// * `InDirection` - cab orders and the hall order in the direction we announce that we leave in.
+///   Passengers waiting to go the other way keep their order until a car going their way arrives.
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ClearPolicy {
+    All,
+    InDirection,
+}
+
+/// Fire service recall, where the elevators are taken out of normal service.
+/// * `PhaseOne` - we go nonstop to the recall floor `floor`, open the door and stay there. All orders are cancelled.
+/// * `PhaseTwo` - a firefighter runs the car from inside, only cab orders are taken.
+///   When phase two ends, we go back to the recall floor.
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize, Hash)]
+pub enum Recall {
+    Off,
+    PhaseOne { floor: u8 },
+    PhaseTwo { floor: u8 },
+}
+
+/// The building-wide recall switch, as it was last flipped from any of the nodes.
+/// Every node broadcasts the newest one it knows of, so all nodes end up agreeing.
+#[derive(Copy, Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize, Hash)]
+pub struct RecallSignal {
+    pub version: u64,
+    pub active: bool,
+}
+
+impl RecallSignal {
+    /// A higher version wins. If two nodes flip the switch at the same time, turning recall on wins.
+    pub fn is_newer_than(&self, other: &RecallSignal) -> bool {
+        return (self.version, self.active) > (other.version, other.active);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum Event {
     OnDoorTimeOut,
     OnFloorArrival { floor: u8 },
     OnNewOrder { btn: poll::CallButton },
     OnObstructionSignal { active: bool },
     OnStateTimeOut,
+    OnHardwareStatus { status: elevio::HardwareStatus },
+    OnStopButton { pressed: bool },
+    /// We have been idle for a while, and should wait at `floor` instead
+    OnPark { floor: u8 },
+    /// The recall switch changed somewhere in the building. `floor` is where we go if it is on.
+    OnRecall { signal: RecallSignal, floor: u8 },
+    /// The firefighter switch in the car, only used during recall
+    OnPhaseTwo { active: bool },
+    /// The load of the car changed, in percent of its capacity
+    OnLoad { percent: u8 },
+    /// A peer gave back our cab orders from when they had `version`. The orders follow as `OnNewOrder`.
+    OnCabBackupRestored { version: u64 },
+    /// Another car has acknowledged our hall order `btn` and takes it from here
+    OnHandOver { btn: poll::CallButton },
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize, Hash, PartialEq)]
@@ -40,32 +98,162 @@ pub struct ElevatorInfo {
     pub dirn: u8,
     pub floor: u8,
     pub responsible_orders: order_list::OrderList,
+    /// Shared with the other cars, so the cost function simulates every car with its own policy
+    pub clear_policy: ClearPolicy,
+    pub recall: Recall,
+    pub recall_signal: RecallSignal,
+    /// The floors this car can reach, so a building can have cars of different heights or banks
+    pub served_floors: Vec<u8>,
+    /// Floors this car passes without stopping, even if it could reach them
+    pub locked_floors: Vec<u8>,
+    /// In percent of the capacity of the car
+    pub load: u8,
+    pub full_load_percent: u8,
+    /// Goes up every time our cab orders change, so a backup of them can be told apart from an older one
+    pub cab_version: u64,
+}
+
+/// Something the fsm wants done as the result of an event
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Hardware(elevio::HardwareCommand),
+    DoorTimer(TimerCommand),
+    /// The state was entered or re-entered, used for detecting motor and obstruction timeouts
+    StateUpdate(State),
+    /// The door opened and we told the passengers which way we are going next.
+    /// There is no lantern on the hardware, so this is only logged.
+    DirectionAnnounced { floor: u8, dirn: u8 },
+    /// We passed a hall order because we are full, and someone else should take it
+    ReleaseOrder(poll::CallButton),
 }
 
 impl ElevatorInfo {
+    pub fn new(config: &Config) -> ElevatorInfo {
+        ElevatorInfo {
+            id: config.id(),
+            state: State::Initializing,
+            dirn: elevio::DIRN_DOWN,
+            floor: u8::MAX,
+            responsible_orders: order_list::OrderList::new(config.num_floors),
+            clear_policy: config.order_clearing,
+            recall: Recall::Off,
+            recall_signal: RecallSignal::default(),
+            served_floors: config.get_served_floors(),
+            locked_floors: config.locked_floors.clone(),
+            load: 0,
+            full_load_percent: config.full_load_percent,
+            cab_version: 0,
+        }
+    }
+
+    /// A full car passes hall calls, there is no room for the people waiting
+    pub fn is_full(&self) -> bool {
+        return self.load >= self.full_load_percent;
+    }
+
+    /// Says if the car can take orders to `floor`
+    pub fn serves(&self, floor: u8) -> bool {
+        return self.served_floors.contains(&floor) && !self.locked_floors.contains(&floor);
+    }
+
     pub fn get_id(&self) -> usize {
-        return self.clone().id;
+        return self.id;
+    }
+
+    /// Takes back the active cab orders and the floor saved before a restart. The hall orders are dispatched
+    /// again instead, see `state_file.rs`. Orders to floors we no longer serve are dropped.
+    fn restore(&mut self, saved: &SavedState) {
+        for f in 0..self.responsible_orders.inside_queue.len() as u8 {
+            let btn = poll::CallButton { floor: f, call: poll::CAB };
+            if saved.orders.is_active(btn) && self.serves(f) {
+                self.responsible_orders.set_active(btn);
+            }
+        }
+        if let Some(floor) = saved.floor {
+            self.floor = floor;
+        }
+        self.cab_version = saved.cab_version;
+    }
+}
+
+#[cfg(test)]
+impl ElevatorInfo {
+    /// Car `id` standing idle at `floor`, with the default settings. The starting point of the tests in every module.
+    pub(crate) fn idle_at(id: usize, floor: u8) -> ElevatorInfo {
+        let mut info = ElevatorInfo::new(&Config { id: Some(id), ..Default::default() });
+        info.state = State::Idle;
+        info.floor = floor;
+        return info;
     }
 }
 
-/// Contains all we need to know about our elevator.
+/// Takes the elevator from one state to the next.
+///
+/// This is the whole fsm, without any side effects: the returned actions say which hardware
+/// commands, timer commands and state updates should be carried out, in order.
+/// `Elevator` carries them out on channels, while the cost function just throws them away.
+pub fn step(info: &ElevatorInfo, event: Event) -> (ElevatorInfo, Vec<Action>) {
+    let mut core = Core {
+        info: info.clone(),
+        actions: Vec::new(),
+    };
+    match event {
+        Event::OnDoorTimeOut => core.on_door_time_out(),
+        Event::OnFloorArrival { floor } => core.on_floor_arrival(floor),
+        Event::OnNewOrder { btn } => core.on_new_order(btn),
+        Event::OnObstructionSignal { active } => core.on_obstruction_signal(active),
+        Event::OnStateTimeOut => core.on_state_timeout(),
+        Event::OnHardwareStatus { status } => core.on_hardware_status(status),
+        Event::OnStopButton { pressed } => core.on_stop_button(pressed),
+        Event::OnPark { floor } => core.on_park(floor),
+        Event::OnRecall { signal, floor } => core.on_recall(signal, floor),
+        Event::OnPhaseTwo { active } => core.on_phase_two(active),
+        Event::OnLoad { percent } => core.info.load = percent,
+        Event::OnCabBackupRestored { version } => core.info.cab_version = core.info.cab_version.max(version),
+        Event::OnHandOver { btn } => core.on_hand_over(btn),
+    }
+    if core.info.responsible_orders.inside_queue != info.responsible_orders.inside_queue {
+        core.info.cab_version += 1;
+    }
+    return (core.info, core.actions);
+}
+
+/// The timers the fsm needs, all run by the same timer service
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FsmTimer {
+    Door,
+    /// Runs while we are trying to reach a floor, to detect motor power loss
+    MotorWatchdog,
+    /// Runs while the door is obstructed, so our orders can be given to someone else
+    Obstruction,
+    /// Runs while we are idle, we go to our home floor when it runs out
+    Parking,
+}
+
+/// Contains all we need to know about our elevator, and carries out the actions of the fsm.
 /// * `hw_tx` the transmitter for sending hardware commands
-/// * `timer_start_tx` the transmitter for starting the door timer
-/// * `state_update_tx` the transmitter for door 
+/// * `timer_tx` the transmitter for starting and cancelling the timers in `FsmTimer`
+/// * `released_orders_tx` the transmitter for hall orders we passed because the car is full
 /// * `info` information about the elevator: `state`, `dirn`, `floor` and `responsible_orders`
 #[derive(Clone, Debug)]
 pub struct Elevator {
     hw_tx: crossbeam_channel::Sender<elevio::HardwareCommand>,
-    timer_start_tx: cbc::Sender<TimerCommand>,
-    state_update_tx: cbc::Sender<State>,
+    timer_tx: cbc::Sender<TimerRequest<FsmTimer>>,
+    released_orders_tx: cbc::Sender<poll::CallButton>,
+    door_open_duration: time::Duration,
+    motor_timeout: time::Duration,
+    obstruction_timeout: time::Duration,
+    parking_idle_time: Option<time::Duration>,
     info: ElevatorInfo,
 }
 
 impl Elevator {
     pub fn new(
         hw_commander: cbc::Sender<elevio::HardwareCommand>,
-        timer_start_tx: cbc::Sender<TimerCommand>,
-        state_updater_tx: cbc::Sender<State>,
+        timer_tx: cbc::Sender<TimerRequest<FsmTimer>>,
+        released_orders_tx: cbc::Sender<poll::CallButton>,
+        restored: Option<SavedState>,
+        config: &Config,
     ) -> Elevator {
         hw_commander
             .send(elevio::HardwareCommand::MotorDirection {
@@ -73,7 +261,7 @@ impl Elevator {
             })
             .unwrap();
         // Disable all lights when we first start
-        for f in 0..setting::ELEV_NUM_FLOORS {
+        for f in 0..config.num_floors {
             for c in 0..3 {
                 hw_commander.send(elevio::HardwareCommand::CallButtonLight{floor: f, call: c, on: false}).unwrap();
             }
@@ -81,143 +269,225 @@ impl Elevator {
         hw_commander.send(elevio::HardwareCommand::StopLight{on: false}).unwrap();
         hw_commander.send(elevio::HardwareCommand::DoorLight{on: false}).unwrap();   
 
-        return Elevator {
+        let mut info = ElevatorInfo::new(config);
+        if let Some(saved) = restored {
+            info.restore(&saved);
+            if info.floor != u8::MAX {
+                hw_commander.send(elevio::HardwareCommand::FloorLight { floor: info.floor }).unwrap();
+            }
+        }
+
+        let elevator = Elevator {
             hw_tx: hw_commander,
-            timer_start_tx: timer_start_tx,
-            state_update_tx: state_updater_tx,
-            info: ElevatorInfo {
-                id: setting::ID,
-                state: State::Initializing,
-                dirn: elevio::DIRN_DOWN,
-                floor: u8::MAX,
-                responsible_orders: order_list::OrderList::new(setting::ELEV_NUM_FLOORS),
-            },
+            timer_tx,
+            released_orders_tx,
+            door_open_duration: config.door_open_duration(),
+            motor_timeout: config.motor_timeout(),
+            obstruction_timeout: config.obstruction_timeout(),
+            parking_idle_time: config.parking_idle_time(),
+            info,
         };
+        // We are moving down to find a floor
+        elevator.watch_state(State::Initializing);
+        return elevator;
     }
     /// Takes the elevator fsm from one state to the next and sends the appropriate hardware commands on the hardware channel
-    #[allow(unreachable_patterns)]
     pub fn on_event(&mut self, event: Event) {
-        match event {
-            Event::OnDoorTimeOut => self.on_door_time_out(),
-            Event::OnFloorArrival { floor } => self.on_floor_arrival(floor),
-            Event::OnNewOrder { btn } => self.on_new_order(btn),
-            Event::OnObstructionSignal { active } => self.on_obstruction_signal(active),
-            Event::OnStateTimeOut => self.on_state_timeout(),
-            _ => panic!("Invalid event: {:#?}", event),
+        let (info, actions) = step(&self.info, event);
+        self.info = info;
+        for action in actions {
+            match action {
+                Action::Hardware(cmd) => self.hw_tx.send(cmd).unwrap(),
+                Action::DoorTimer(TimerCommand::Start) => self.start_timer(FsmTimer::Door, self.door_open_duration),
+                Action::DoorTimer(TimerCommand::Cancel) => self.cancel_timer(FsmTimer::Door),
+                Action::StateUpdate(state) => self.watch_state(state),
+                Action::DirectionAnnounced { floor, dirn } => {
+                    println!("Door open at floor {}, going {}", floor, direction_name(dirn));
+                }
+                Action::ReleaseOrder(btn) => self.released_orders_tx.send(btn).unwrap(),
+            }
         }
     }
 
-    pub fn create_simulation_elevator(
-        info: ElevatorInfo,
-        hw_commander: cbc::Sender<elevio::HardwareCommand>,
-        timer_start_tx: cbc::Sender<TimerCommand>,
-        state_updater_tx: cbc::Sender<State>,
-    ) -> Elevator {
-        return Elevator {
-            hw_tx: hw_commander,
-            timer_start_tx: timer_start_tx,
-            state_update_tx: state_updater_tx,
-            info: info.clone(),
-        };
+    /// (Re)starts the watchdog of the state we just entered, and stops the other one.
+    /// Either one running out is reported to the fsm as `Event::OnStateTimeOut`.
+    /// The parking timer runs only while we are idle.
+    fn watch_state(&self, state: State) {
+        match state {
+            State::Obstructed => {
+                self.cancel_timer(FsmTimer::MotorWatchdog);
+                self.start_timer(FsmTimer::Obstruction, self.obstruction_timeout);
+            }
+            State::Moving | State::Initializing | State::Parking { .. } => {
+                self.cancel_timer(FsmTimer::Obstruction);
+                self.start_timer(FsmTimer::MotorWatchdog, self.motor_timeout);
+            }
+            _ => {
+                self.cancel_timer(FsmTimer::Obstruction);
+                self.cancel_timer(FsmTimer::MotorWatchdog);
+            }
+        }
+        match (state, self.parking_idle_time) {
+            (State::Idle, Some(idle_time)) => self.start_timer(FsmTimer::Parking, idle_time),
+            _ => self.cancel_timer(FsmTimer::Parking),
+        }
+    }
+
+    fn start_timer(&self, name: FsmTimer, duration: time::Duration) {
+        self.timer_tx.send(TimerRequest::Start { name, duration }).unwrap();
+    }
+
+    fn cancel_timer(&self, name: FsmTimer) {
+        self.timer_tx.send(TimerRequest::Cancel { name }).unwrap();
     }
 
     pub fn get_info(&self) -> ElevatorInfo {
         return self.info.clone();
     }
     pub fn get_floor(&self) -> u8 {
-        return self.get_info().floor;
+        return self.info.floor;
     }
     pub fn get_state(&self) -> State {
-        return self.get_info().state;
+        return self.info.state;
     }
     pub fn get_dirn(&self) -> u8 {
-        return self.get_info().dirn;
+        return self.info.dirn;
     }
     pub fn get_hw_tx_handle(&self) -> cbc::Sender<elevio::HardwareCommand> {
         return self.hw_tx.clone();
     }
     pub fn get_orders(&self) -> order_list::OrderList {
-        return self.get_info().responsible_orders;
+        return self.info.responsible_orders.clone();
+    }
+}
+
+/// The elevator while it is handling a single event
+struct Core {
+    info: ElevatorInfo,
+    actions: Vec<Action>,
+}
+
+impl Core {
+    fn get_floor(&self) -> u8 {
+        return self.info.floor;
+    }
+    fn get_state(&self) -> State {
+        return self.info.state;
+    }
+    fn get_dirn(&self) -> u8 {
+        return self.info.dirn;
+    }
+
+    fn hw(&mut self, cmd: elevio::HardwareCommand) {
+        self.actions.push(Action::Hardware(cmd));
+    }
+    fn door_timer(&mut self, cmd: TimerCommand) {
+        self.actions.push(Action::DoorTimer(cmd));
+    }
+    fn state_update(&mut self, state: State) {
+        self.actions.push(Action::StateUpdate(state));
+    }
+    /// Decides which way we leave the floor and tells the passengers, when only orders in that direction are cleared
+    fn announce_direction(&mut self) {
+        if self.info.clear_policy == ClearPolicy::InDirection {
+            self.info.dirn = direction_decider::announce_direction(&self.info);
+            self.actions.push(Action::DirectionAnnounced { floor: self.info.floor, dirn: self.info.dirn });
+        }
     }
 
     fn on_door_time_out(&mut self) {
         let state = self.get_state();
-        match state {
-            State::DoorOpen => {
-                self.hw_tx
-                    .send(elevio::HardwareCommand::DoorLight { on: false })
-                    .unwrap();
-                self.info
-                    .responsible_orders
-                    .clear_orders_on_floor(self.get_floor());
-                let new_dirn: u8 = direction_decider::choose_direction(self);
-                self.hw_tx
-                    .send(elevio::HardwareCommand::MotorDirection { dirn: new_dirn })
-                    .unwrap();
-                if new_dirn == elevio::DIRN_STOP {
-                    self.info.state = State::Idle;
-                    self.state_update_tx.send(State::Idle).unwrap();
-                } else {
-                    self.info.dirn = new_dirn;
-                    self.info.state = State::Moving;
-                    self.state_update_tx.send(State::Moving).unwrap();
+        if state == State::DoorOpen {
+            if let Recall::PhaseOne { floor } = self.info.recall {
+                // At the recall floor the door stays open
+                if self.get_floor() != floor {
+                    self.hw(elevio::HardwareCommand::DoorLight { on: false });
+                    self.go_to_recall_floor(floor);
                 }
+                return;
+            }
+            direction_decider::clear_orders_at_floor(&mut self.info);
+            // Nobody wants to continue our way, so we keep the door open for those going the other way
+            if let Some(new_dirn) = direction_decider::turn_around_direction(&self.info) {
+                self.info.dirn = new_dirn;
+                self.announce_direction();
+                self.door_timer(TimerCommand::Start);
+                self.state_update(State::DoorOpen);
+                return;
+            }
+            self.hw(elevio::HardwareCommand::DoorLight { on: false });
+            let new_dirn: u8 = direction_decider::choose_direction(&self.info);
+            self.hw(elevio::HardwareCommand::MotorDirection { dirn: new_dirn });
+            if new_dirn == elevio::DIRN_STOP {
+                self.info.state = State::Idle;
+                self.state_update(State::Idle);
+            } else {
+                self.info.dirn = new_dirn;
+                self.info.state = State::Moving;
+                self.state_update(State::Moving);
             }
-            _ => {}
         }
     }
 
     fn on_floor_arrival(&mut self, new_floor: u8) {
         let state = self.get_state();
         self.info.floor = new_floor;
-        self.hw_tx
-            .send(elevio::HardwareCommand::FloorLight { floor: new_floor })
-            .unwrap();
+        self.hw(elevio::HardwareCommand::FloorLight { floor: new_floor });
         match state {
             State::Moving => {
-                if direction_decider::should_stop(self) {
-                    self.hw_tx
-                        .send(elevio::HardwareCommand::MotorDirection {
-                            dirn: elevio::DIRN_STOP,
-                        })
-                        .unwrap();
-                    self.hw_tx
-                        .send(elevio::HardwareCommand::DoorLight { on: true })
-                        .unwrap();
+                if self.info.is_full() {
+                    self.release_hall_orders_at_floor();
+                }
+                if let Recall::PhaseOne { floor } = self.info.recall {
+                    self.go_to_recall_floor(floor);
+                } else if direction_decider::should_stop(&self.info) && !self.info.serves(new_floor) {
+                    self.leave_unserved_floor();
+                } else if direction_decider::should_stop(&self.info) {
+                    self.hw(elevio::HardwareCommand::MotorDirection { dirn: elevio::DIRN_STOP });
+                    self.hw(elevio::HardwareCommand::DoorLight { on: true });
                     self.info.state = State::DoorOpen;
-                    self.state_update_tx.send(State::DoorOpen).unwrap();
+                    self.state_update(State::DoorOpen);
+                    self.announce_direction();
                     //Start timer
-                    self.timer_start_tx.send(TimerCommand::Start).unwrap();
+                    self.door_timer(TimerCommand::Start);
                 } else {
-                    self.state_update_tx.send(State::Moving).unwrap();
+                    self.state_update(State::Moving);
+                }
+            }
+            State::Parking { floor } => {
+                if new_floor == floor {
+                    self.hw(elevio::HardwareCommand::MotorDirection { dirn: elevio::DIRN_STOP });
+                    self.info.state = State::Idle;
                 }
+                self.state_update(self.info.state);
             }
             State::Initializing => {
-                self.hw_tx
-                .send(elevio::HardwareCommand::DoorLight { on: true })
-                .unwrap();
-                self.hw_tx
-                .send(elevio::HardwareCommand::MotorDirection {
-                    dirn: elevio::DIRN_STOP,
-                }).unwrap();
+                // Hall orders may have been left pending if we lost the hardware
+                self.info.responsible_orders.change_all_assigned_hall_order_status(OrderType::Active);
+                if !self.info.serves(new_floor) {
+                    self.leave_unserved_floor();
+                    return;
+                }
+                self.hw(elevio::HardwareCommand::DoorLight { on: true });
+                self.hw(elevio::HardwareCommand::MotorDirection { dirn: elevio::DIRN_STOP });
                 self.info.state = State::DoorOpen;
-                self.state_update_tx.send(State::DoorOpen).unwrap();
-                self.timer_start_tx.send(TimerCommand::Start).unwrap();
+                self.state_update(State::DoorOpen);
+                self.announce_direction();
+                self.door_timer(TimerCommand::Start);
             }
             State::MovTimedOut => {
                 self.info.responsible_orders.change_all_assigned_hall_order_status(OrderType::Active);
-                self.hw_tx
-                    .send(elevio::HardwareCommand::MotorDirection {
-                        dirn: elevio::DIRN_STOP,
-                    })
-                    .unwrap();
-                self.hw_tx
-                    .send(elevio::HardwareCommand::DoorLight { on: true })
-                    .unwrap();
+                if !self.info.serves(new_floor) {
+                    self.leave_unserved_floor();
+                    return;
+                }
+                self.hw(elevio::HardwareCommand::MotorDirection { dirn: elevio::DIRN_STOP });
+                self.hw(elevio::HardwareCommand::DoorLight { on: true });
                 self.info.state = State::DoorOpen;
-                self.state_update_tx.send(State::DoorOpen).unwrap();
+                self.state_update(State::DoorOpen);
+                self.announce_direction();
                 //Start timer
-                self.timer_start_tx.send(TimerCommand::Start).unwrap();
+                self.door_timer(TimerCommand::Start);
             }
             _ => {}
         }
@@ -225,40 +495,69 @@ impl Elevator {
 
     fn on_new_order(&mut self, btn: poll::CallButton) {
         let state = self.get_state();
+        if !self.info.serves(btn.floor) {
+            return;
+        }
+        match (self.info.recall, btn.call) {
+            (Recall::PhaseOne { .. }, _) => return,
+            (Recall::PhaseTwo { .. }, call) if call != poll::CAB => return,
+            _ => {}
+        }
 
         match state {
             State::DoorOpen => {
-                if self.get_floor() == btn.floor {
+                self.info.responsible_orders.set_active(btn);
+                // Passengers going the other way have to wait until we turn around
+                if self.get_floor() == btn.floor && direction_decider::served_at_open_door(&self.info, btn) {
                     //start timer
-                    self.timer_start_tx.send(TimerCommand::Start).unwrap();
+                    self.door_timer(TimerCommand::Start);
+                    if self.get_dirn() == elevio::DIRN_STOP && btn.call != poll::CAB {
+                        self.announce_direction();
+                    }
                 }
-                self.info.responsible_orders.set_active(btn);
             }
-            State::Obstructed | State::Moving | State::ObstrTimedOut => {
+            State::Obstructed | State::Moving | State::ObstrTimedOut | State::EmergencyStop { .. } => {
                 self.info.responsible_orders.set_active(btn);
             }
 
             State::Idle => {
                 self.info.responsible_orders.set_active(btn);
                 if self.get_floor() == btn.floor {
-                    self.hw_tx
-                        .send(elevio::HardwareCommand::DoorLight { on: true })
-                        .unwrap();
-                    self.timer_start_tx.send(TimerCommand::Start).unwrap();
+                    self.hw(elevio::HardwareCommand::DoorLight { on: true });
+                    self.door_timer(TimerCommand::Start);
                     self.info.state = State::DoorOpen;
-                    self.state_update_tx.send(State::DoorOpen).unwrap();
+                    self.state_update(State::DoorOpen);
+                    self.announce_direction();
                 } else {
-                    let new_dirn: u8 = direction_decider::choose_direction(self);
-                    self.hw_tx
-                        .send(elevio::HardwareCommand::MotorDirection { dirn: new_dirn })
-                        .unwrap();
+                    let new_dirn: u8 = direction_decider::choose_direction(&self.info);
+                    self.hw(elevio::HardwareCommand::MotorDirection { dirn: new_dirn });
                     self.info.state = State::Moving;
-                    self.state_update_tx.send(State::Moving).unwrap();
+                    self.state_update(State::Moving);
                     self.info.dirn = new_dirn;
                 }
             }
 
-            State::Initializing | State::MovTimedOut => {}
+            // Forget about parking, and head for the order. We have already left `floor`, so an order there is behind us,
+            // and the poller reports `floor` again when we are back on its sensor.
+            State::Parking { .. } => {
+                self.info.responsible_orders.set_active(btn);
+                let new_dirn = if btn.floor == self.get_floor() {
+                    reverse(self.get_dirn())
+                } else {
+                    direction_decider::choose_direction(&self.info)
+                };
+                self.hw(elevio::HardwareCommand::MotorDirection { dirn: new_dirn });
+                self.info.dirn = new_dirn;
+                self.info.state = State::Moving;
+                self.state_update(State::Moving);
+            }
+
+            // Kept until we find a floor, e.g. cab orders backed up by the other cars while we were gone
+            State::Initializing => {
+                self.info.responsible_orders.set_active(btn);
+            }
+
+            State::MovTimedOut => {}
         }
     }
 
@@ -267,14 +566,14 @@ impl Elevator {
         if state == State::DoorOpen || state == State::Obstructed || state == State::ObstrTimedOut {
             match active {
                 true => {
-                    self.timer_start_tx.send(TimerCommand::Cancel).unwrap();
+                    self.door_timer(TimerCommand::Cancel);
                     self.info.state = State::Obstructed;
-                    self.state_update_tx.send(State::Obstructed).unwrap();
+                    self.state_update(State::Obstructed);
                 }
                 false => {
-                    self.timer_start_tx.send(TimerCommand::Start).unwrap();
+                    self.door_timer(TimerCommand::Start);
                     self.info.state = State::DoorOpen;
-                    self.state_update_tx.send(State::DoorOpen).unwrap();
+                    self.state_update(State::DoorOpen);
                 }
             }
         }
@@ -285,59 +584,420 @@ impl Elevator {
         match state {
             State::Obstructed => {
                 self.info.state = State::ObstrTimedOut;
-                self.state_update_tx.send(State::ObstrTimedOut).unwrap();
+                self.state_update(State::ObstrTimedOut);
             }
-            State::Moving | State::Initializing => {
+            State::Moving | State::Initializing | State::Parking { .. } => {
                 self.info.state = State::MovTimedOut;
                 self.info.responsible_orders.change_all_assigned_hall_order_status(OrderType::Pending);
-                self.state_update_tx.send(State::MovTimedOut).unwrap();
+                self.state_update(State::MovTimedOut);
             }
             _ => {}
         }
     }
-}
 
-pub fn create_simulation_elevator(
-    elev_info: ElevatorInfo,
-    dummy_hw_tx: cbc::Sender<elevio::HardwareCommand>,
-    dummy_timer_start_tx: cbc::Sender<TimerCommand>,
-    dummy_state_updater_tx: cbc::Sender<State>,
-) -> Elevator {
-    return Elevator {
-        hw_tx: dummy_hw_tx,
-        timer_start_tx: dummy_timer_start_tx,
-        state_update_tx: dummy_state_updater_tx,
-        info: elev_info.clone(),
-    };
-}
+    /// Pressing the stop button halts the car immediately, and opens the door if we are at a floor.
+    /// Our hall orders are given up the same way as on a motor timeout.
+    ///
+    /// When the button is released, we take our hall orders back and continue where we left off:
+    /// * if the door is open, it closes after the normal door time
+    /// * if we stopped between floors on the way to our home floor, and got no orders meanwhile, we go on parking
+    /// * if we stopped between floors otherwise, we continue in the direction we were going
+    /// * if we never found a floor, we start initializing again
+    fn on_stop_button(&mut self, pressed: bool) {
+        let state = self.get_state();
+        match (pressed, state) {
+            (true, State::EmergencyStop { .. }) => {}
+            (true, _) => {
+                let at_floor = matches!(state, State::Idle | State::DoorOpen | State::Obstructed | State::ObstrTimedOut);
+                let parking = match state {
+                    State::Parking { floor } => Some(floor),
+                    _ => None,
+                };
+                self.hw(elevio::HardwareCommand::MotorDirection { dirn: elevio::DIRN_STOP });
+                self.hw(elevio::HardwareCommand::StopLight { on: true });
+                self.hw(elevio::HardwareCommand::DoorLight { on: at_floor });
+                self.door_timer(TimerCommand::Cancel);
+                self.info.responsible_orders.change_all_assigned_hall_order_status(OrderType::Pending);
+                self.info.state = State::EmergencyStop { door_open: at_floor, parking };
+                self.state_update(self.info.state);
+            }
+            (false, State::EmergencyStop { door_open, parking }) => {
+                self.hw(elevio::HardwareCommand::StopLight { on: false });
+                self.info.responsible_orders.change_all_assigned_hall_order_status(OrderType::Active);
+                if door_open {
+                    self.info.state = State::DoorOpen;
+                    self.announce_direction();
+                    self.door_timer(TimerCommand::Start);
+                } else if self.get_floor() == u8::MAX {
+                    self.info.dirn = elevio::DIRN_DOWN;
+                    self.info.state = State::Initializing;
+                } else if let (Some(floor), elevio::DIRN_STOP) = (parking, direction_decider::choose_direction(&self.info)) {
+                    self.info.state = State::Parking { floor };
+                } else {
+                    self.info.state = State::Moving;
+                }
+                if self.info.state != State::DoorOpen {
+                    self.hw(elevio::HardwareCommand::MotorDirection { dirn: self.get_dirn() });
+                }
+                self.state_update(self.info.state);
+            }
+            (false, _) => {
+                self.hw(elevio::HardwareCommand::StopLight { on: false });
+            }
+        }
+    }
 
+    fn on_park(&mut self, floor: u8) {
+        if self.get_state() != State::Idle || self.get_floor() == floor || self.info.recall != Recall::Off {
+            return;
+        }
+        let dirn = if floor > self.get_floor() { elevio::DIRN_UP } else { elevio::DIRN_DOWN };
+        self.hw(elevio::HardwareCommand::MotorDirection { dirn });
+        self.info.dirn = dirn;
+        self.info.state = State::Parking { floor };
+        self.state_update(self.info.state);
+    }
 
-pub fn state_timeout_checker(state_updater_rx: cbc::Receiver<State>, elev_timeout_tx: cbc::Sender<()>) {
-    let mut when_state_updated = time::Instant::now();
-    let mut timeout_duration = time::Duration::from_secs(setting::MOTOR_TIMEOUT_DURATION_SEC);
-    loop {
-        match state_updater_rx.try_recv() {
-            Ok(val) => {
-                when_state_updated = time::Instant::now();
-                let state = val;
+    /// Drops a hall order another car has taken. If we are already serving it with the door open, it is too late
+    /// to give it away, and the passengers will see it served twice. A moving car that has nothing left stops at the next floor.
+    fn on_hand_over(&mut self, btn: poll::CallButton) {
+        if btn.call == poll::CAB || (self.get_state() == State::DoorOpen && self.get_floor() == btn.floor) {
+            return;
+        }
+        self.info.responsible_orders.remove_order(btn);
+    }
+
+    /// Entering recall cancels all our orders. Cars that are busy with a door, an obstruction or a fault
+    /// head for the recall floor the next time the door closes or they reach a floor.
+    fn on_recall(&mut self, signal: RecallSignal, floor: u8) {
+        self.info.recall_signal = signal;
+        let state = self.get_state();
+        match (signal.active, self.info.recall) {
+            (true, Recall::Off) => {
+                self.info.recall = Recall::PhaseOne { floor };
+                self.info.responsible_orders.clear_all_orders();
                 match state {
-                    State::Obstructed => {
-                        timeout_duration = time::Duration::from_secs(setting::OBSTRUCTED_TIME_BEFORE_REASSIGN_SEC);
-                    },
-                    State::Moving | State::Initializing => {
-                        timeout_duration = time::Duration::from_secs(setting::MOTOR_TIMEOUT_DURATION_SEC);
-                    },
-                    _ => {
-                        timeout_duration = time::Duration::new(u64::MAX,0);
-                    },
+                    State::Idle => self.go_to_recall_floor(floor),
+                    State::Parking { .. } => {
+                        self.info.state = State::Moving;
+                        self.state_update(State::Moving);
+                    }
+                    _ => {}
+                }
+            }
+            (false, Recall::PhaseOne { .. }) | (false, Recall::PhaseTwo { .. }) => {
+                self.info.recall = Recall::Off;
+                // Close the door we have been holding open at the recall floor
+                if state == State::DoorOpen {
+                    self.door_timer(TimerCommand::Start);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn on_phase_two(&mut self, active: bool) {
+        let state = self.get_state();
+        match (active, self.info.recall) {
+            (true, Recall::PhaseOne { floor }) => {
+                self.info.recall = Recall::PhaseTwo { floor };
+                if state == State::DoorOpen {
+                    self.door_timer(TimerCommand::Start);
                 }
-            },
+            }
+            (false, Recall::PhaseTwo { floor }) => {
+                self.info.recall = Recall::PhaseOne { floor };
+                self.info.responsible_orders.clear_all_orders();
+                if state == State::Idle {
+                    self.go_to_recall_floor(floor);
+                }
+            }
             _ => {}
         }
-        let now = time::Instant::now();
-        if now.duration_since(when_state_updated) > timeout_duration {
-            elev_timeout_tx.send(()).unwrap();
+    }
+
+    /// Gives up the hall orders on this floor, so they can be taken by a car with room for the passengers
+    fn release_hall_orders_at_floor(&mut self) {
+        for call in [poll::HALL_UP, poll::HALL_DOWN] {
+            let btn = poll::CallButton { floor: self.get_floor(), call };
+            if self.info.responsible_orders.is_active(btn) {
+                self.info.responsible_orders.remove_order(btn);
+                self.actions.push(Action::ReleaseOrder(btn));
+            }
+        }
+    }
+
+    /// We may not open the door here, so we go straight on to the next order, or wait with the door closed
+    fn leave_unserved_floor(&mut self) {
+        let new_dirn = direction_decider::choose_direction(&self.info);
+        self.hw(elevio::HardwareCommand::MotorDirection { dirn: new_dirn });
+        if new_dirn == elevio::DIRN_STOP {
+            self.info.state = State::Idle;
+        } else {
+            self.info.dirn = new_dirn;
+            self.info.state = State::Moving;
+        }
+        self.state_update(self.info.state);
+    }
+
+    /// Opens the door if we are at the recall floor, and heads there without stopping otherwise
+    fn go_to_recall_floor(&mut self, floor: u8) {
+        if self.get_floor() == floor {
+            self.hw(elevio::HardwareCommand::MotorDirection { dirn: elevio::DIRN_STOP });
+            self.hw(elevio::HardwareCommand::DoorLight { on: true });
+            self.info.state = State::DoorOpen;
+        } else {
+            let dirn = if floor > self.get_floor() { elevio::DIRN_UP } else { elevio::DIRN_DOWN };
+            self.hw(elevio::HardwareCommand::MotorDirection { dirn });
+            self.info.dirn = dirn;
+            self.info.state = State::Moving;
         }
+        self.state_update(self.info.state);
+    }
+
+    /// Without the hardware we do not know where the car is, so we give up our hall orders
+    /// the same way as on a motor timeout. When the hardware is back, we find our floor again.
+    /// A car held by the stop button has given up its orders already, and stays stopped until the button is
+    /// released. The button is read again when the hardware is back, before this event.
+    fn on_hardware_status(&mut self, status: elevio::HardwareStatus) {
+        let state = self.get_state();
+        match status {
+            elevio::HardwareStatus::Unavailable => {
+                if state != State::MovTimedOut && !matches!(state, State::EmergencyStop { .. }) {
+                    self.info.state = State::MovTimedOut;
+                    self.info.responsible_orders.change_all_assigned_hall_order_status(OrderType::Pending);
+                    self.state_update(State::MovTimedOut);
+                }
+            }
+            elevio::HardwareStatus::Available => {
+                if state == State::MovTimedOut {
+                    self.hw(elevio::HardwareCommand::DoorLight { on: false });
+                    self.hw(elevio::HardwareCommand::MotorDirection { dirn: elevio::DIRN_DOWN });
+                    self.info.dirn = elevio::DIRN_DOWN;
+                    self.info.state = State::Initializing;
+                    self.state_update(State::Initializing);
+                }
+            }
+        }
+    }
+}
+
+fn reverse(dirn: u8) -> u8 {
+    match dirn {
+        elevio::DIRN_UP => elevio::DIRN_DOWN,
+        elevio::DIRN_DOWN => elevio::DIRN_UP,
+        _ => elevio::DIRN_STOP,
     }
 }
 
+fn direction_name(dirn: u8) -> &'static str {
+    match dirn {
+        elevio::DIRN_UP => "up",
+        elevio::DIRN_DOWN => "down",
+        _ => "nowhere",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local_elevator::elevio::elev::{HardwareCommand, HardwareStatus, DIRN_DOWN, DIRN_STOP, DIRN_UP};
+    use crate::local_elevator::elevio::poll::{CallButton, CAB, HALL_DOWN, HALL_UP};
+
+    /// Runs the events in order, and returns the elevator with the actions of the last one
+    fn run(info: &ElevatorInfo, events: &[Event]) -> (ElevatorInfo, Vec<Action>) {
+        let mut result = (info.clone(), Vec::new());
+        for &event in events {
+            result = step(&result.0, event);
+        }
+        return result;
+    }
+
+    fn order(floor: u8, call: u8) -> Event {
+        return Event::OnNewOrder { btn: CallButton { floor, call } };
+    }
+
+    fn motor(dirn: u8) -> Action {
+        return Action::Hardware(HardwareCommand::MotorDirection { dirn });
+    }
+
+    #[test]
+    fn door_opens_on_arrival_at_an_order() {
+        let (info, actions) = step(&ElevatorInfo::idle_at(1, 0), order(2, CAB));
+        assert_eq!(info.state, State::Moving);
+        assert!(actions.contains(&motor(DIRN_UP)));
+
+        let (info, actions) = step(&info, Event::OnFloorArrival { floor: 1 });
+        assert_eq!(info.state, State::Moving);
+        assert!(!actions.contains(&motor(DIRN_STOP)));
+
+        let (info, actions) = step(&info, Event::OnFloorArrival { floor: 2 });
+        assert_eq!(info.state, State::DoorOpen);
+        assert!(actions.contains(&motor(DIRN_STOP)));
+        assert!(actions.contains(&Action::Hardware(HardwareCommand::DoorLight { on: true })));
+        assert!(actions.contains(&Action::DoorTimer(TimerCommand::Start)));
+
+        let (info, actions) = step(&info, Event::OnDoorTimeOut);
+        assert_eq!(info.state, State::Idle);
+        assert!(!info.responsible_orders.is_active(CallButton { floor: 2, call: CAB }));
+        assert!(actions.contains(&Action::Hardware(HardwareCommand::DoorLight { on: false })));
+    }
+
+    #[test]
+    fn order_at_the_floor_of_an_idle_car_opens_the_door() {
+        let (info, actions) = step(&ElevatorInfo::idle_at(1, 1), order(1, HALL_UP));
+        assert_eq!(info.state, State::DoorOpen);
+        assert!(actions.contains(&Action::DoorTimer(TimerCommand::Start)));
+    }
+
+    #[test]
+    fn obstruction_holds_the_door_open() {
+        let (door_open, _) = step(&ElevatorInfo::idle_at(1, 1), order(1, CAB));
+        let (info, actions) = step(&door_open, Event::OnObstructionSignal { active: true });
+        assert_eq!(info.state, State::Obstructed);
+        assert!(actions.contains(&Action::DoorTimer(TimerCommand::Cancel)));
+
+        assert_eq!(step(&info, Event::OnDoorTimeOut).0.state, State::Obstructed);
+        let (info, _) = step(&info, Event::OnStateTimeOut);
+        assert_eq!(info.state, State::ObstrTimedOut);
+
+        let (info, actions) = step(&info, Event::OnObstructionSignal { active: false });
+        assert_eq!(info.state, State::DoorOpen);
+        assert!(actions.contains(&Action::DoorTimer(TimerCommand::Start)));
+    }
+
+    #[test]
+    fn obstruction_is_ignored_with_the_door_closed() {
+        let (info, _) = step(&ElevatorInfo::idle_at(1, 0), order(3, CAB));
+        assert_eq!(step(&info, Event::OnObstructionSignal { active: true }).0.state, State::Moving);
+    }
+
+    #[test]
+    fn stop_button_between_floors_gives_up_hall_orders_until_released() {
+        let hall_order = CallButton { floor: 3, call: HALL_DOWN };
+        let (moving, _) = run(&ElevatorInfo::idle_at(1, 0), &[order(3, HALL_DOWN), Event::OnFloorArrival { floor: 1 }]);
+        let (info, actions) = step(&moving, Event::OnStopButton { pressed: true });
+        assert_eq!(info.state, State::EmergencyStop { door_open: false, parking: None });
+        assert!(actions.contains(&motor(DIRN_STOP)));
+        assert!(actions.contains(&Action::Hardware(HardwareCommand::DoorLight { on: false })));
+        assert!(info.responsible_orders.is_pending(hall_order));
+
+        let (info, actions) = step(&info, Event::OnStopButton { pressed: false });
+        assert_eq!(info.state, State::Moving);
+        assert!(actions.contains(&motor(DIRN_UP)));
+        assert!(info.responsible_orders.is_active(hall_order));
+    }
+
+    #[test]
+    fn stop_button_at_a_floor_opens_the_door() {
+        let (info, actions) = step(&ElevatorInfo::idle_at(1, 2), Event::OnStopButton { pressed: true });
+        assert_eq!(info.state, State::EmergencyStop { door_open: true, parking: None });
+        assert!(actions.contains(&Action::Hardware(HardwareCommand::DoorLight { on: true })));
+
+        let (info, actions) = step(&info, Event::OnStopButton { pressed: false });
+        assert_eq!(info.state, State::DoorOpen);
+        assert!(actions.contains(&Action::DoorTimer(TimerCommand::Start)));
+        assert!(!actions.iter().any(|action| matches!(action, Action::Hardware(HardwareCommand::MotorDirection { .. }))));
+    }
+
+    #[test]
+    fn emergency_stop_is_kept_through_a_hardware_loss() {
+        let (info, _) = step(&ElevatorInfo::idle_at(1, 2), Event::OnStopButton { pressed: true });
+        let (info, _) = step(&info, Event::OnHardwareStatus { status: HardwareStatus::Unavailable });
+        assert_eq!(info.state, State::EmergencyStop { door_open: true, parking: None });
+        let (info, actions) = step(&info, Event::OnHardwareStatus { status: HardwareStatus::Available });
+        assert_eq!(info.state, State::EmergencyStop { door_open: true, parking: None });
+        assert!(!actions.contains(&motor(DIRN_DOWN)));
+    }
+
+    #[test]
+    fn recall_goes_to_the_recall_floor_and_holds_the_door_open() {
+        let signal = RecallSignal { version: 1, active: true };
+        let mut idle = ElevatorInfo::idle_at(1, 2);
+        idle.responsible_orders.set_active(CallButton { floor: 3, call: CAB });
+        let (info, actions) = step(&idle, Event::OnRecall { signal, floor: 0 });
+        assert_eq!(info.recall, Recall::PhaseOne { floor: 0 });
+        assert_eq!(info.state, State::Moving);
+        assert!(actions.contains(&motor(DIRN_DOWN)));
+        assert!(!info.responsible_orders.is_active(CallButton { floor: 3, call: CAB }));
+
+        // Nonstop past the other floors, and no new orders
+        let (info, _) = run(&info, &[order(1, CAB), Event::OnFloorArrival { floor: 2 }, Event::OnFloorArrival { floor: 1 }]);
+        assert_eq!(info.state, State::Moving);
+        let (info, _) = step(&info, Event::OnFloorArrival { floor: 0 });
+        assert_eq!(info.state, State::DoorOpen);
+        let (info, actions) = step(&info, Event::OnDoorTimeOut);
+        assert_eq!(info.state, State::DoorOpen);
+        assert!(actions.is_empty());
+
+        // The firefighter takes cab orders only
+        let (info, _) = run(&info, &[Event::OnPhaseTwo { active: true }, order(2, HALL_UP), order(2, CAB)]);
+        assert_eq!(info.recall, Recall::PhaseTwo { floor: 0 });
+        assert!(!info.responsible_orders.is_active(CallButton { floor: 2, call: HALL_UP }));
+        assert!(info.responsible_orders.is_active(CallButton { floor: 2, call: CAB }));
+    }
+
+    #[test]
+    fn recall_ends_with_the_door_closing() {
+        let on = RecallSignal { version: 1, active: true };
+        let off = RecallSignal { version: 2, active: false };
+        let (info, actions) = run(&ElevatorInfo::idle_at(1, 0), &[Event::OnRecall { signal: on, floor: 0 }, Event::OnRecall { signal: off, floor: 0 }]);
+        assert_eq!(info.recall, Recall::Off);
+        assert!(actions.contains(&Action::DoorTimer(TimerCommand::Start)));
+        assert_eq!(step(&info, Event::OnDoorTimeOut).0.state, State::Idle);
+    }
+
+    #[test]
+    fn parking_trip_ends_idle_at_the_home_floor() {
+        let (info, actions) = step(&ElevatorInfo::idle_at(1, 0), Event::OnPark { floor: 2 });
+        assert_eq!(info.state, State::Parking { floor: 2 });
+        assert!(actions.contains(&motor(DIRN_UP)));
+
+        let (info, _) = step(&info, Event::OnFloorArrival { floor: 1 });
+        assert_eq!(info.state, State::Parking { floor: 2 });
+        let (info, actions) = step(&info, Event::OnFloorArrival { floor: 2 });
+        assert_eq!(info.state, State::Idle);
+        assert!(actions.contains(&motor(DIRN_STOP)));
+    }
+
+    #[test]
+    fn parking_is_only_started_when_idle_and_not_in_recall() {
+        let (moving, _) = step(&ElevatorInfo::idle_at(1, 0), order(3, CAB));
+        assert_eq!(step(&moving, Event::OnPark { floor: 2 }).0.state, State::Moving);
+        assert_eq!(step(&ElevatorInfo::idle_at(1, 2), Event::OnPark { floor: 2 }).0.state, State::Idle);
+    }
+
+    #[test]
+    fn new_order_ends_the_parking_trip_and_steers_the_motor() {
+        let (parking, _) = step(&ElevatorInfo::idle_at(1, 1), Event::OnPark { floor: 3 });
+
+        let (info, actions) = step(&parking, order(3, CAB));
+        assert_eq!((info.state, info.dirn), (State::Moving, DIRN_UP));
+        assert!(actions.contains(&motor(DIRN_UP)));
+
+        let (info, actions) = step(&parking, order(0, HALL_UP));
+        assert_eq!((info.state, info.dirn), (State::Moving, DIRN_DOWN));
+        assert!(actions.contains(&motor(DIRN_DOWN)));
+
+        // We have already left floor 1, and turn back to it
+        let (info, actions) = step(&parking, order(1, CAB));
+        assert_eq!((info.state, info.dirn), (State::Moving, DIRN_DOWN));
+        assert!(actions.contains(&motor(DIRN_DOWN)));
+        let (info, _) = step(&info, Event::OnFloorArrival { floor: 1 });
+        assert_eq!(info.state, State::DoorOpen);
+    }
+
+    #[test]
+    fn stop_button_during_parking_resumes_the_trip() {
+        let (parking, _) = step(&ElevatorInfo::idle_at(1, 0), Event::OnPark { floor: 3 });
+        let (info, _) = step(&parking, Event::OnStopButton { pressed: true });
+        assert_eq!(info.state, State::EmergencyStop { door_open: false, parking: Some(3) });
+
+        let (info, actions) = step(&info, Event::OnStopButton { pressed: false });
+        assert_eq!(info.state, State::Parking { floor: 3 });
+        assert!(actions.contains(&motor(DIRN_UP)));
+
+        let (info, _) = run(&parking, &[Event::OnStopButton { pressed: true }, order(2, CAB), Event::OnStopButton { pressed: false }]);
+        assert_eq!(info.state, State::Moving);
+    }
+}
@@ -15,12 +15,12 @@ pub enum OrderType {
 ///
 /// # Example
 /// ```rust
-/// use elevator::elevio::poll::CallButton;
-/// use elevator::order_manager::order_list::OrderList;
+/// use elevator::local_elevator::elevio::poll::CallButton;
+/// use elevator::local_elevator::fsm::order_list::OrderList;
 /// let num_floors = 4;
 /// let mut elevator_orders = OrderList::new(num_floors);
 /// let call_button_corresponding_to_order = CallButton{floor: 2, call: 1};
-/// elevator_orders.set_active(call_button_corresponding_to_order)
+/// elevator_orders.set_active(call_button_corresponding_to_order);
 /// ```
 ///
 #[derive(PartialEq, Clone, Debug, serde::Serialize, serde::Deserialize, Hash)]
@@ -0,0 +1,172 @@
+//! Keeps the orders and floor of the local elevator on disk, so they survive a crash or a power loss.
+//!
+//! The state is written to a temporary file which is then renamed over the old one,
+//! so a crash while writing leaves either the old or the new state, never half of one.
+//!
+//! On startup the saved cab orders are given to the fsm before it starts initializing. The saved hall orders
+//! may have been taken over by another car while we were gone, so they are dispatched again like a new press.
+//! The other elevators may also hold a backup of our cab orders (see `order_assigner/cab_backup.rs`),
+//! which is only used if it is newer than what we saved ourselves.
+use serde;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use crossbeam_channel as cbc;
+
+use crate::local_elevator::elevio::poll::{CallButton, CAB};
+use crate::local_elevator::fsm::elevatorfsm::ElevatorInfo;
+use crate::local_elevator::fsm::order_list::OrderList;
+use crate::util::config::Config;
+
+/// What we remember about the local elevator
+/// * `orders` the orders we were responsible for, only the active ones are restored
+/// * `floor` the last floor we were at, if we had reached one
+/// * `cab_version` the version of our cab orders, see `order_assigner/cab_backup.rs`
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SavedState {
+    pub orders: OrderList,
+    pub floor: Option<u8>,
+    #[serde(default)]
+    pub cab_version: u64,
+}
+
+impl SavedState {
+    pub fn from_info(info: &ElevatorInfo) -> SavedState {
+        SavedState {
+            orders: info.responsible_orders.clone(),
+            floor: Some(info.floor).filter(|&f| f != u8::MAX),
+            cab_version: info.cab_version,
+        }
+    }
+
+    /// The active hall orders, to be dispatched again
+    pub fn hall_orders(&self) -> Vec<CallButton> {
+        let mut hall_orders = Vec::new();
+        for floor in 0..self.orders.inside_queue.len() as u8 {
+            for call in 0..CAB {
+                let btn = CallButton { floor, call };
+                if self.orders.is_active(btn) {
+                    hall_orders.push(btn);
+                }
+            }
+        }
+        return hall_orders;
+    }
+}
+
+/// Where the state of this node is kept, or `None` if `state_dir` is empty
+pub fn path(config: &Config) -> Option<PathBuf> {
+    if config.state_dir.is_empty() {
+        return None;
+    }
+    return Some(Path::new(&config.state_dir).join(format!("elevator-{}.state", config.id())));
+}
+
+/// Reads the saved state, if there is one.
+/// A state saved with another number of floors can not be used, and is reported as invalid data.
+pub fn load(path: &Path, config: &Config) -> io::Result<Option<SavedState>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let state: SavedState = serde_json::from_str(&contents)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let num_floors = usize::from(config.num_floors);
+    if state.orders.inside_queue.len() != num_floors || state.floor.is_some_and(|f| f >= config.num_floors) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("saved state does not match num_floors = {}", num_floors)));
+    }
+    return Ok(Some(state));
+}
+
+/// Atomically replaces the saved state with `state`
+pub fn save(path: &Path, state: &SavedState) -> io::Result<()> {
+    let tmp_path = path.with_extension("state.tmp");
+    let contents = serde_json::to_vec(state)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&contents)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    return Ok(());
+}
+
+/// Saves the state of the local elevator every time its orders or floor change
+pub fn journal(local_info_rx: cbc::Receiver<ElevatorInfo>, path: PathBuf) {
+    let mut saved: Option<SavedState> = None;
+    for info in local_info_rx.iter() {
+        let state = SavedState::from_info(&info);
+        if saved.as_ref() == Some(&state) {
+            continue;
+        }
+        match save(&path, &state) {
+            Ok(()) => saved = Some(state),
+            Err(err) => println!("Could not save state to {}: {}", path.display(), err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local_elevator::elevio::poll::HALL_DOWN;
+
+    fn temp_path(name: &str) -> PathBuf {
+        return std::env::temp_dir().join(format!("{}-{}.state", name, std::process::id()));
+    }
+
+    fn saved_state() -> SavedState {
+        let mut info = ElevatorInfo::idle_at(1, 2);
+        info.responsible_orders.set_active(CallButton { floor: 0, call: CAB });
+        info.responsible_orders.set_active(CallButton { floor: 3, call: HALL_DOWN });
+        info.responsible_orders.set_pending(true, CallButton { floor: 1, call: HALL_DOWN });
+        info.cab_version = 7;
+        return SavedState::from_info(&info);
+    }
+
+    #[test]
+    fn saved_state_is_loaded_back() {
+        let path = temp_path("round-trip");
+        let state = saved_state();
+        save(&path, &state).unwrap();
+        let loaded = load(&path, &Config::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, Some(state));
+        assert_eq!(loaded.unwrap().hall_orders(), vec![CallButton { floor: 3, call: HALL_DOWN }]);
+        assert!(!path.with_extension("state.tmp").exists());
+    }
+
+    #[test]
+    fn nothing_saved_is_not_an_error() {
+        assert_eq!(load(&temp_path("missing"), &Config::default()).unwrap(), None);
+    }
+
+    #[test]
+    fn corrupt_file_is_invalid_data() {
+        let path = temp_path("corrupt");
+        let contents = serde_json::to_string(&saved_state()).unwrap();
+        fs::write(&path, &contents[..contents.len() / 2]).unwrap();
+        let result = load(&path, &Config::default());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn state_of_another_building_is_invalid_data() {
+        let path = temp_path("mismatched");
+        save(&path, &saved_state()).unwrap();
+        let fewer_floors = load(&path, &Config { num_floors: 3, ..Default::default() });
+        let more_floors = load(&path, &Config { num_floors: 6, ..Default::default() });
+        save(&path, &SavedState { floor: Some(4), ..saved_state() }).unwrap();
+        let floor_above_the_top = load(&path, &Config::default());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(fewer_floors.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(more_floors.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(floor_above_the_top.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,78 +1,75 @@
-use std::env;
+#![allow(clippy::needless_return)]
+
 use std::thread::*;
-use std::time;
 use elevator::*;
 use crossbeam_channel as cbc;
 
-use util::constants as setting;
+use util::config::Config;
 use local_elevator::elevio::elev as e;
-use local_elevator::fsm::door_timer;
-use local_elevator::fsm::elevatorfsm::{Elevator, Event, ElevatorInfo, State};
+use local_elevator::elevio::elev::ElevatorIo;
+use util::timer::TimerRequest;
+use util::console::ConsoleCommand;
+use local_elevator::fsm::elevatorfsm::{Elevator, Event, ElevatorInfo, FsmTimer, RecallSignal};
 use global_elevator_info::connected_elevators::ConnectedElevatorInfo;
-use local_elevator::elevio::poll::CallButton;
+use global_elevator_info::elev_info_updater::RemoteUpdate;
+use global_elevator_info::hall_orders::HallOrders;
+use order_assigner::cab_backup::CabBackup;
+use order_assigner::order_protocol::{OrderEvent, OrderId, OrderMessage};
+use local_elevator::elevio::poll::{CallButton, HardwareEvent, PollSettings};
 
 fn main() -> std::io::Result<()> {
-    if setting::ID > setting::MAX_NUM_ELEV {
-        panic!("Trying to start an elevator with an ID that is too high. Consider increasing MAX_NUM_ELEV in util/constants.rs");
-    }
-
-    println!("Elevator started with local ID: {}", setting::ID);
-    // To run on a simulator port, call "cargo run PORT_TO_RUN_ON"
-    let args: Vec<String> = env::args().collect();
-    let mut server_port: String = "15657".to_string();
-    if args.len() > 1 {
-        server_port = args[1].clone();
-    }
-    let elev_hw_server: String = format!("{}:{}", "localhost", server_port);
+    // To run on a simulator port, call "cargo run PORT_TO_RUN_ON". See util/config.rs for all settings.
+    let mut config = match Config::from_env() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
 
-    // We must wait to assure that, in the case of software restart, the system is registered as lost:
-    std::thread::sleep(std::time::Duration::from_millis(setting::TIME_UNTIL_PEER_LOST_MILLISEC+500));
+    // Everything below needs the id, so it is claimed before anything else starts
+    config.id = Some(global_elevator_info::node_id::claim(&config)?);
+    let incarnation = global_elevator_info::elev_info_updater::new_incarnation(&config)?;
+    println!("Elevator started with local ID: {}", config.id());
 
     /*--------------------SINGLE ELEVATOR---------------------*/
-    let elevator = e::ElevatorHW::init(&elev_hw_server[..], setting::ELEV_NUM_FLOORS)?;
+    let (hw_status_tx, hw_status_rx) = cbc::unbounded::<e::HardwareStatus>();
+    let elevator = e::ElevatorHW::init(&config.hw_server[..], config.num_floors, hw_status_tx)?;
     println!("Elevator started:\n{:#?}", elevator);
 
     /* Initialization of hardware polling */
-    let poll_period = time::Duration::from_millis(25);
-    let (call_button_tx, call_button_rx) = cbc::unbounded::<CallButton>();
-    {
-        let elevator = elevator.clone();
-        spawn(move || 
-            local_elevator::elevio::poll::call_buttons(elevator, call_button_tx, poll_period));
-    }
-
-    let (floor_sensor_tx, floor_sensor_rx) = cbc::unbounded::<u8>();
-    {
-        let elevator = elevator.clone();
-        spawn(move || 
-            local_elevator::elevio::poll::floor_sensor(elevator, floor_sensor_tx, poll_period));
-    }
-
-    let (stop_button_tx, stop_button_rx) = cbc::unbounded::<bool>();
+    let (hw_event_tx, hw_event_rx) = cbc::unbounded::<HardwareEvent>();
     {
         let elevator = elevator.clone();
+        let poll_settings = PollSettings::from_config(&config);
         spawn(move || 
-            local_elevator::elevio::poll::stop_button(elevator, stop_button_tx, poll_period));
+            local_elevator::elevio::poll::run(elevator, hw_event_tx, poll_settings));
     }
+    let (call_button_tx, call_button_rx) = cbc::unbounded::<CallButton>();
 
-    let (obstruction_tx, obstruction_rx) = cbc::unbounded::<bool>();
-    {
-        let elevator = elevator.clone();
-        spawn(move || 
-            local_elevator::elevio::poll::obstruction(elevator, obstruction_tx, poll_period));
-    }    
-
-    /* Thread that keeps track of the local elevator door timer */
-    let (door_timer_start_tx, door_timer_start_rx) = cbc::unbounded::<door_timer::TimerCommand>();
-    let (door_timeout_tx, door_timeout_rx) = cbc::unbounded::<()>();
-    spawn(move || {
-        door_timer::run(door_timer_start_rx, door_timeout_tx);
-    });
+    /* Thread that runs the door timer and the motor and obstruction watchdogs */
+    let (timer_tx, timer_rx) = cbc::unbounded::<TimerRequest<FsmTimer>>();
+    let (timer_expired_tx, timer_expired_rx) = cbc::unbounded::<FsmTimer>();
+    spawn(move || util::timer::run(timer_rx, timer_expired_tx));
 
     /* Initialization of the local elevator fsm */
     let (hardware_command_tx, hardware_command_rx) = cbc::unbounded::<e::HardwareCommand>();
-    let (state_updater_tx, state_updater_rx) = cbc::unbounded::<State>();
-    let mut fsm = Elevator::new(hardware_command_tx.clone(), door_timer_start_tx, state_updater_tx);
+    let (released_orders_tx, released_orders_rx) = cbc::unbounded::<CallButton>();
+    // Orders from before a crash or restart are taken back before we start looking for a floor
+    let state_path = local_elevator::fsm::state_file::path(&config);
+    let restored = match state_path.as_ref().map(|path| local_elevator::fsm::state_file::load(path, &config)) {
+        Some(Ok(saved)) => saved,
+        Some(Err(err)) => {
+            println!("Could not load saved state, starting without it: {}", err);
+            None
+        }
+        None => None,
+    };
+    // Someone else may have taken our hall orders while we were gone, so they go to the best fit car again
+    for btn in restored.iter().flat_map(|saved| saved.hall_orders()) {
+        call_button_tx.send(btn).unwrap();
+    }
+    let mut fsm = Elevator::new(hardware_command_tx.clone(), timer_tx, released_orders_tx, restored, &config);
     let (local_elev_info_tx, local_elev_info_rx) = cbc::unbounded::<ElevatorInfo>();
     let (assign_orders_locally_tx, assign_orders_locally_rx) = cbc::unbounded::<CallButton>();
 
@@ -80,32 +77,41 @@ fn main() -> std::io::Result<()> {
     {
         let elevator = elevator.clone();
         spawn(move || loop {
-            let r = hardware_command_rx.recv();
-            match r {
-                Ok(cmd) => elevator.execute_command(cmd),
-                Err(_e) => {}
+            if let Ok(cmd) = hardware_command_rx.recv() {
+                // While disconnected, the driver remembers the command and re-issues it on reconnect
+                match elevator.execute_command(cmd) {
+                    Ok(()) | Err(e::HardwareError::Disconnected) => {},
+                    Err(err) => println!("Failed to execute {:?}: {}", cmd, err),
+                }
             }
         });
     }
 
     // Global elevator info manager
     let (local_info_for_global_tx, local_info_for_global_rx) = cbc::unbounded::<local_elevator::fsm::elevatorfsm::ElevatorInfo>();
-    let (remote_update_tx, remote_update_rx) = cbc::unbounded::<Vec<local_elevator::fsm::elevatorfsm::ElevatorInfo>>();
+    let (remote_update_tx, remote_update_rx) = cbc::unbounded::<RemoteUpdate>();
+    let (new_hall_orders_tx, new_hall_orders_rx) = cbc::unbounded::<CallButton>();
+    let (hall_orders_tx, hall_orders_rx) = cbc::unbounded::<HallOrders>();
     let (connected_info_tx, connected_info_rx) = cbc::unbounded::<ConnectedElevatorInfo>();
     let (connected_info_for_assigner_tx,connected_info_for_assigner_rx) = cbc::unbounded::<ConnectedElevatorInfo>();
     let (connected_info_for_lights_tx, connected_info_for_lights_rx) = cbc::unbounded::<ConnectedElevatorInfo>();
+    let (connected_info_for_parking_tx, connected_info_for_parking_rx) = cbc::unbounded::<ConnectedElevatorInfo>();
     let (set_pending_tx, set_pending_rx) = cbc::unbounded::<(bool,usize,CallButton)>();
+    let (handed_over_tx, handed_over_rx) = cbc::unbounded::<CallButton>();
+    let (drop_order_tx, drop_order_rx) = cbc::unbounded::<CallButton>();
     {
         let alc_tx = assign_orders_locally_tx.clone();
+        let config = config.clone();
         spawn(move || 
-            global_elevator_info::connected_elevators::connected_elevator_info(local_info_for_global_rx, remote_update_rx, set_pending_rx, connected_info_tx, alc_tx));
+            global_elevator_info::connected_elevators::connected_elevator_info(local_info_for_global_rx, remote_update_rx, set_pending_rx, new_hall_orders_rx, connected_info_tx, hall_orders_tx, alc_tx, released_orders_rx, handed_over_rx, drop_order_tx, &config));
     }
     local_info_for_global_tx.send(fsm.get_info()).unwrap();
     
     {
         let order_lights_tx = hardware_command_tx.clone();
+        let config = config.clone();
         spawn(move || 
-            global_elevator_info::connected_elevators::set_order_lights(connected_info_for_lights_rx, order_lights_tx));
+            global_elevator_info::connected_elevators::set_order_lights(connected_info_for_lights_rx, order_lights_tx, &config));
     }
     
 
@@ -113,41 +119,63 @@ fn main() -> std::io::Result<()> {
 
     /* Transmit local elevator info on network */
     let (local_elev_info_to_transmit_tx, local_elev_info_to_transmit_rx) = cbc::unbounded::<ElevatorInfo>();
-    spawn(move || 
-            global_elevator_info::elev_info_updater::local_elev_info_tx::<ElevatorInfo>(local_elev_info_to_transmit_rx)
+    {
+        let config = config.clone();
+        spawn(move || 
+            global_elevator_info::elev_info_updater::local_elev_info_tx::<ElevatorInfo>(local_elev_info_to_transmit_rx, hall_orders_rx, incarnation, &config)
         );
+    }
     local_elev_info_to_transmit_tx.send(fsm.get_info()).unwrap();
 
     /* Receive elevator info from remote elevators */
-    let (backup_cab_order_transmitter_tx, backup_cab_order_transmitter_rx) = cbc::unbounded::<ElevatorInfo>();
-    spawn(move || 
-        global_elevator_info::elev_info_updater::remote_elev_info_rx::<Vec<ElevatorInfo>>(remote_update_tx, backup_cab_order_transmitter_tx)
-    );
+    let (id_conflict_tx, id_conflict_rx) = cbc::unbounded::<u64>();
+    {
+        let config = config.clone();
+        spawn(move || 
+            global_elevator_info::elev_info_updater::remote_elev_info_rx::<Vec<ElevatorInfo>>(remote_update_tx, id_conflict_tx, incarnation, &config)
+        );
+    }
 
     /* Transmit and receive orders to/from other elevators */
+    let (order_replies_tx, order_replies_rx) = cbc::unbounded::<OrderMessage>();
+    let (assigned_orders_tx, assigned_orders_rx) = cbc::unbounded::<(OrderId, CallButton)>();
+    let (order_accepted_tx, order_accepted_rx) = cbc::unbounded::<(OrderId, bool)>();
+    let (order_events_tx, order_events_rx) = cbc::unbounded::<OrderEvent>();
+    spawn(move || order_assigner::order_protocol::log_events(order_events_rx));
     {
         let set_pending_transmitter = set_pending_tx.clone();
         let local_order_assign_tx = assign_orders_locally_tx.clone();
+        let order_events_tx = order_events_tx.clone();
+        let config = config.clone();
         spawn(move || 
-            order_assigner::order_transmitter::hall_order_transmitter(connected_info_for_assigner_rx, call_button_rx, set_pending_transmitter, local_order_assign_tx));
+            order_assigner::order_transmitter::hall_order_transmitter(connected_info_for_assigner_rx, call_button_rx, order_replies_rx, set_pending_transmitter, new_hall_orders_tx, local_order_assign_tx, handed_over_tx, order_events_tx, &config));
     }
 
     {
-        let local_order_assign_tx = assign_orders_locally_tx.clone();
+        let config = config.clone();
         spawn(move || 
-            order_assigner::order_receiver::hall_order_receiver(local_order_assign_tx));
+            order_assigner::order_receiver::hall_order_receiver(assigned_orders_tx, order_accepted_rx, order_replies_tx, order_events_tx, &config));
     }
 
-    /* Transmit and receive cab_order backup to/from remote elevators */
+    /* Back up our cab orders to the remote elevators, and hold theirs */
+    let (local_info_for_backup_tx, local_info_for_backup_rx) = cbc::unbounded::<ElevatorInfo>();
+    let (cab_backup_restored_tx, cab_backup_restored_rx) = cbc::unbounded::<CabBackup>();
+    {
+        let config = config.clone();
+        spawn(move || 
+            order_assigner::cab_backup::run(local_info_for_backup_rx, cab_backup_restored_tx, &config)
+        );
+    }
+    local_info_for_backup_tx.send(fsm.get_info()).unwrap();
 
-    spawn(move || 
-        order_assigner::order_receiver::cab_order_backup_rx::<Vec<ElevatorInfo>>(assign_orders_locally_tx)
-    );
 
-    spawn(move || 
-        order_assigner::order_transmitter::cab_order_backup_tx::<ElevatorInfo>(backup_cab_order_transmitter_rx)
-    );
 
+    /* Journal of the local orders and floor */
+    let (local_info_for_journal_tx, local_info_for_journal_rx) = cbc::unbounded::<ElevatorInfo>();
+    if let Some(path) = state_path {
+        spawn(move || local_elevator::fsm::state_file::journal(local_info_for_journal_rx, path));
+    }
+    let _ = local_info_for_journal_tx.send(fsm.get_info());
 
 
     /*--------------------UTILITY---------------------*/
@@ -159,12 +187,16 @@ fn main() -> std::io::Result<()> {
                     let glob_info = a.unwrap();
                     connected_info_for_assigner_tx.send(glob_info.clone()).unwrap();
                     connected_info_for_lights_tx.send(glob_info.clone()).unwrap();
+                    connected_info_for_parking_tx.send(glob_info.clone()).unwrap();
 
                 },
                 recv(local_elev_info_rx) -> a => {
                     let local_info = a.unwrap();
                     local_info_for_global_tx.send(local_info.clone()).unwrap();
                     local_elev_info_to_transmit_tx.send(local_info.clone()).unwrap();
+                    local_info_for_backup_tx.send(local_info.clone()).unwrap();
+                    // Nobody listens if the state is not saved
+                    let _ = local_info_for_journal_tx.send(local_info.clone());
                 }
             }
         }
@@ -172,11 +204,10 @@ fn main() -> std::io::Result<()> {
 
     /*----------------LOOP FOR LOCAL ELEVATOR INPUT---------------------*/
 
-    let (elev_timeout_tx, elev_timeout_rx) = cbc::unbounded::<()>();
-    spawn(move || 
-        local_elevator::fsm::elevatorfsm::state_timeout_checker(state_updater_rx, elev_timeout_tx)
-    );
-    
+    let (console_tx, mut console_rx) = cbc::unbounded::<ConsoleCommand>();
+    spawn(move || util::console::run(console_tx));
+
+    let mut connected_info: Option<ConnectedElevatorInfo> = None;
     loop {
         cbc::select! {
             recv(assign_orders_locally_rx) -> a => {
@@ -185,27 +216,119 @@ fn main() -> std::io::Result<()> {
                 fsm.on_event(Event::OnNewOrder{btn: call_button});
                 local_elev_info_tx.send(fsm.get_info()).unwrap();         
             },
-            recv(floor_sensor_rx) -> a => {
-                let floor = a.unwrap();
-                fsm.on_event(Event::OnFloorArrival{floor: floor});
-                println!("Floor: {:#?}", floor);
+            // Orders from other elevators are only acknowledged if the fsm takes them
+            recv(assigned_orders_rx) -> a => {
+                let (order_id, call_button) = a.unwrap();
+                fsm.on_event(Event::OnNewOrder{btn: call_button});
+                order_accepted_tx.send((order_id, fsm.get_orders().is_active(call_button))).unwrap();
                 local_elev_info_tx.send(fsm.get_info()).unwrap();
             },
-            recv(stop_button_rx) -> a => {
-                let _stop = a.unwrap();
+            recv(drop_order_rx) -> a => {
+                fsm.on_event(Event::OnHandOver{btn: a.unwrap()});
                 local_elev_info_tx.send(fsm.get_info()).unwrap();
             },
-            recv(obstruction_rx) -> a => {
-                let obstr = a.unwrap();
-                fsm.on_event(Event::OnObstructionSignal{active: obstr});
+            recv(hw_event_rx) -> a => {
+                match a.unwrap() {
+                    HardwareEvent::CallButton(call_button) => {
+                        call_button_tx.send(call_button).unwrap();
+                    },
+                    HardwareEvent::FloorSensor(floor) => {
+                        fsm.on_event(Event::OnFloorArrival{floor});
+                        println!("Floor: {:#?}", floor);
+                        local_elev_info_tx.send(fsm.get_info()).unwrap();
+                    },
+                    HardwareEvent::StopButton(pressed) => {
+                        fsm.on_event(Event::OnStopButton{pressed});
+                        local_elev_info_tx.send(fsm.get_info()).unwrap();
+                    },
+                    HardwareEvent::Load(percent) => {
+                        fsm.on_event(Event::OnLoad{percent});
+                        local_elev_info_tx.send(fsm.get_info()).unwrap();
+                    },
+                    HardwareEvent::Obstruction(obstr) => {
+                        fsm.on_event(Event::OnObstructionSignal{active: obstr});
+                        local_elev_info_tx.send(fsm.get_info()).unwrap();
+                    },
+                }
+            },
+            recv(timer_expired_rx) -> a => {
+                match a.unwrap() {
+                    FsmTimer::Door => fsm.on_event(Event::OnDoorTimeOut),
+                    FsmTimer::MotorWatchdog | FsmTimer::Obstruction => fsm.on_event(Event::OnStateTimeOut),
+                    FsmTimer::Parking => {
+                        let home_floor = connected_info.as_ref()
+                            .and_then(|info| order_assigner::parking::find_home_floor(info, &fsm.get_info(), &config));
+                        if let Some(floor) = home_floor {
+                            fsm.on_event(Event::OnPark{floor});
+                        }
+                    },
+                }
                 local_elev_info_tx.send(fsm.get_info()).unwrap();
             },
-            recv(door_timeout_rx) -> _ => {
-                fsm.on_event(Event::OnDoorTimeOut);
+            recv(connected_info_for_parking_rx) -> a => {
+                let info = a.unwrap();
+                // Follow the recall switch when it is flipped on another node
+                let signal = info.get_newest_recall_signal();
+                if signal.is_newer_than(&fsm.get_info().recall_signal) {
+                    fsm.on_event(Event::OnRecall{signal, floor: config.recall_floor});
+                    local_elev_info_tx.send(fsm.get_info()).unwrap();
+                }
+                connected_info = Some(info);
+            },
+            recv(console_rx) -> a => {
+                let cmd = match a {
+                    Ok(cmd) => cmd,
+                    // stdin was closed, e.g. when running in the background
+                    Err(_) => {
+                        console_rx = cbc::never();
+                        continue;
+                    }
+                };
+                match cmd {
+                    ConsoleCommand::Recall{active} => {
+                        let newest = connected_info.as_ref()
+                            .map(|info| info.get_newest_recall_signal())
+                            .filter(|signal| signal.is_newer_than(&fsm.get_info().recall_signal))
+                            .unwrap_or(fsm.get_info().recall_signal);
+                        let signal = RecallSignal{version: newest.version + 1, active};
+                        fsm.on_event(Event::OnRecall{signal, floor: config.recall_floor});
+                    },
+                    ConsoleCommand::PhaseTwo{active} => fsm.on_event(Event::OnPhaseTwo{active}),
+                }
                 local_elev_info_tx.send(fsm.get_info()).unwrap();
             },
-            recv(elev_timeout_rx) -> _ => {
-                fsm.on_event(Event::OnStateTimeOut);
+            recv(cab_backup_restored_rx) -> a => {
+                let backup = a.unwrap();
+                // What we saved ourselves may be newer, and then the backup would bring back served orders
+                if backup.is_newer_than(fsm.get_info().cab_version) {
+                    println!("Restoring cab orders {:?} from backup version {}", backup.floors, backup.version);
+                    fsm.on_event(Event::OnCabBackupRestored{version: backup.version});
+                    for btn in backup.orders() {
+                        fsm.on_event(Event::OnNewOrder{btn});
+                    }
+                    local_elev_info_tx.send(fsm.get_info()).unwrap();
+                }
+            },
+            recv(id_conflict_rx) -> a => {
+                // Two cars with the same id would take each other's orders, so we stop and leave it to a person
+                let other_incarnation = a.unwrap();
+                eprintln!("ALARM: another elevator (incarnation {}) is running with our id {}, refusing to operate",
+                    other_incarnation, config.id());
+                let _ = elevator.execute_command(e::HardwareCommand::MotorDirection{dirn: e::DIRN_STOP});
+                let _ = elevator.execute_command(e::HardwareCommand::StopLight{on: true});
+                std::process::exit(2);
+            },
+            recv(hw_status_rx) -> a => {
+                let status = a.unwrap();
+                // The stop button may have been pressed or released while we were gone, and must be known before we move
+                if status == e::HardwareStatus::Available {
+                    match elevator.stop_button() {
+                        Ok(pressed) => fsm.on_event(Event::OnStopButton{pressed}),
+                        // Lost again, we hear about it when it is back
+                        Err(_) => continue,
+                    }
+                }
+                fsm.on_event(Event::OnHardwareStatus{status});
                 local_elev_info_tx.send(fsm.get_info()).unwrap();
             }
         }
@@ -24,15 +24,37 @@ pub fn tx<T: Clone + serde::Serialize>(port: u16, ch: cbc::Receiver<T>, burst_si
 
 pub fn rx<T: serde::de::DeserializeOwned>(port: u16, ch: cbc::Sender<T>){
     let s = sock::new_rx(port).unwrap();
-    let mut buf = [0; 1024];
+    // The elevator info with the hall orders of a tall building does not fit in 1 kB
+    let mut buf = [0; 8192];
     
     loop {
         let n = s.recv(&mut buf).unwrap();
         let msg = std::str::from_utf8(&buf[..n]).unwrap();
         // Only send the message on crossbeam channel if it actually is the data we want
-        match serde_json::from_str::<T>(&msg) {
+        match serde_json::from_str::<T>(msg) {
             Ok(data) => ch.send(data).unwrap(),
             Err(_) => {println!("Received wrong data type!!")}
         }
     }
-}
\ No newline at end of file
+}
+/// Listens on `port` for `duration`, and returns everything of the right type that was received
+pub fn rx_for<T: serde::de::DeserializeOwned>(port: u16, duration: std::time::Duration) -> Vec<T> {
+    let s = sock::new_rx(port).unwrap();
+    let mut buf = [0; 8192];
+    let deadline = std::time::Instant::now() + duration;
+    let mut received = Vec::new();
+
+    loop {
+        let left = deadline.saturating_duration_since(std::time::Instant::now());
+        if left.is_zero() {
+            return received;
+        }
+        s.set_read_timeout(Some(left)).unwrap();
+        // A timeout ends up here as an error, and the loop then sees that the time is up
+        if let Ok(n) = s.recv(&mut buf) {
+            if let Ok(data) = std::str::from_utf8(&buf[..n]).map(serde_json::from_str::<T>) {
+                received.extend(data.ok());
+            }
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! Backup of the cab orders of every elevator, held by the other elevators.
+//!
+//! Cab orders can only be served by their own car, so they must survive that car crashing.
+//! Every node broadcasts its cab orders on `cab_backup_port` whenever they change, and every
+//! `cab_backup_period_millisec` in case a broadcast was lost. The orders carry the `cab_version`
+//! of the owner, which goes up every time they change.
+//!
+//! When a node starts, it asks the others for its backup. A peer holding a backup answers, and keeps
+//! answering until the owner confirms it got it. While it waits, the peer keeps the backup it has,
+//! so the owner can not overwrite it with the empty orders it started with.
+//! The owner only takes back the orders if the backup is newer than the version it has itself
+//! (see `local_elevator/fsm/state_file.rs`), so calls it already served are not brought back.
+use serde;
+use std::collections::HashMap;
+use std::thread::*;
+use std::time;
+use crossbeam_channel as cbc;
+
+use crate::local_elevator::elevio::poll::{CallButton, CAB};
+use crate::local_elevator::fsm::elevatorfsm::ElevatorInfo;
+use crate::util::config::Config;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum CabBackupMessage {
+    /// The cab orders of `owner`, sent by the owner
+    Backup { owner: usize, version: u64, floors: Vec<u8> },
+    /// `owner` has restarted and wants its backup
+    Request { owner: usize },
+    /// The backup of `owner`, sent by a peer on request
+    Restore { owner: usize, version: u64, floors: Vec<u8> },
+    /// `owner` has got its backup of `version`, and does not need it anymore
+    Confirm { owner: usize, version: u64 },
+}
+
+/// A backup of our own cab orders, given back to us by a peer
+#[derive(Clone, Debug, PartialEq)]
+pub struct CabBackup {
+    pub version: u64,
+    pub floors: Vec<u8>,
+}
+
+impl CabBackup {
+    /// The orders to give the fsm, after `Event::OnCabBackupRestored`
+    pub fn orders(&self) -> Vec<CallButton> {
+        return self.floors.iter().map(|&floor| CallButton { floor, call: CAB }).collect();
+    }
+
+    /// Whether the backup has orders we do not know of, given the `cab_version` we have
+    pub fn is_newer_than(&self, cab_version: u64) -> bool {
+        return self.version > cab_version;
+    }
+}
+
+/// A backup held for another elevator
+struct HeldBackup {
+    version: u64,
+    floors: Vec<u8>,
+    /// The owner has asked for it, and not confirmed that it got it
+    requested: bool,
+}
+
+/// Runs both sides of the backup: sends our own cab orders, and holds the orders of the others.
+///
+/// * `local_info_rx` - our elevator info, every time it changes
+/// * `restored_tx` - where backups of our own orders are sent when a peer gives them back
+pub fn run(local_info_rx: cbc::Receiver<ElevatorInfo>, restored_tx: cbc::Sender<CabBackup>, config: &Config) {
+    let (send_bcast_tx, send_bcast_rx) = cbc::unbounded::<CabBackupMessage>();
+    let (recv_bcast_tx, recv_bcast_rx) = cbc::unbounded::<CabBackupMessage>();
+    {
+        let port = config.cab_backup_port;
+        spawn(move || crate::network_interface::bcast::tx(port, send_bcast_rx, 5));
+        spawn(move || crate::network_interface::bcast::rx(port, recv_bcast_tx));
+    }
+
+    let ticker = cbc::tick(config.cab_backup_period());
+    // We ask for our backup until the peers have had time to notice us
+    let stop_requesting = time::Instant::now() + 2 * config.peer_lost_timeout();
+    let mut own: Option<(u64, Vec<u8>)> = None;
+    let mut held: HashMap<usize, HeldBackup> = HashMap::new();
+
+    send_bcast_tx.send(CabBackupMessage::Request { owner: config.id() }).unwrap();
+    loop {
+        cbc::select! {
+            recv(local_info_rx) -> a => {
+                let info = a.unwrap();
+                let backup = (info.cab_version, cab_floors(&info));
+                // While we ask for our backup, we do not send our own, so the one held by the peers is not replaced
+                if own.as_ref() != Some(&backup) && time::Instant::now() >= stop_requesting {
+                    send_bcast_tx.send(CabBackupMessage::Backup { owner: config.id(), version: backup.0, floors: backup.1.clone() }).unwrap();
+                }
+                own = Some(backup);
+            },
+            recv(ticker) -> _ => {
+                if time::Instant::now() < stop_requesting {
+                    send_bcast_tx.send(CabBackupMessage::Request { owner: config.id() }).unwrap();
+                } else if let Some((version, floors)) = own.clone() {
+                    send_bcast_tx.send(CabBackupMessage::Backup { owner: config.id(), version, floors }).unwrap();
+                }
+                for (&owner, backup) in held.iter().filter(|(_, backup)| backup.requested) {
+                    send_bcast_tx.send(CabBackupMessage::Restore { owner, version: backup.version, floors: backup.floors.clone() }).unwrap();
+                }
+            },
+            recv(recv_bcast_rx) -> a => {
+                match a.unwrap() {
+                    CabBackupMessage::Restore { owner, version, floors } if owner == config.id() => {
+                        restored_tx.send(CabBackup { version, floors }).unwrap();
+                        send_bcast_tx.send(CabBackupMessage::Confirm { owner, version }).unwrap();
+                    },
+                    message => hold(&mut held, message, config.id()),
+                }
+            }
+        }
+    }
+}
+
+/// Keeps the backups of the others up to date with a message from the network
+fn hold(held: &mut HashMap<usize, HeldBackup>, message: CabBackupMessage, own_id: usize) {
+    match message {
+        CabBackupMessage::Backup { owner, version, floors } if owner != own_id => {
+            let requested = held.get(&owner).is_some_and(|backup| backup.requested);
+            if !requested {
+                held.insert(owner, HeldBackup { version, floors, requested: false });
+            }
+        },
+        CabBackupMessage::Request { owner } if owner != own_id => {
+            if let Some(backup) = held.get_mut(&owner) {
+                backup.requested = true;
+            }
+        },
+        CabBackupMessage::Confirm { owner, version } if owner != own_id => {
+            if let Some(backup) = held.get_mut(&owner) {
+                if backup.version <= version {
+                    backup.requested = false;
+                }
+            }
+        },
+        _ => {},
+    }
+}
+
+fn cab_floors(info: &ElevatorInfo) -> Vec<u8> {
+    return (0..info.responsible_orders.inside_queue.len() as u8)
+        .filter(|&floor| info.responsible_orders.is_active(CallButton { floor, call: CAB }))
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local_elevator::fsm::elevatorfsm::{step, Event};
+
+    fn backup(owner: usize, version: u64, floors: Vec<u8>) -> CabBackupMessage {
+        return CabBackupMessage::Backup { owner, version, floors };
+    }
+
+    fn held_for(held: &HashMap<usize, HeldBackup>, owner: usize) -> Option<(u64, Vec<u8>, bool)> {
+        return held.get(&owner).map(|backup| (backup.version, backup.floors.clone(), backup.requested));
+    }
+
+    #[test]
+    fn requested_backup_is_kept_until_the_owner_confirms_it() {
+        let mut held = HashMap::new();
+        hold(&mut held, backup(2, 5, vec![1, 3]), 1);
+        hold(&mut held, CabBackupMessage::Request { owner: 2 }, 1);
+        // The owner has restarted with no orders, which must not replace the ones we hold for it
+        hold(&mut held, backup(2, 0, vec![]), 1);
+        assert_eq!(held_for(&held, 2), Some((5, vec![1, 3], true)));
+
+        // A confirm of an older version is for a backup we no longer hold
+        hold(&mut held, CabBackupMessage::Confirm { owner: 2, version: 4 }, 1);
+        assert_eq!(held_for(&held, 2), Some((5, vec![1, 3], true)));
+
+        hold(&mut held, CabBackupMessage::Confirm { owner: 2, version: 5 }, 1);
+        assert_eq!(held_for(&held, 2), Some((5, vec![1, 3], false)));
+        hold(&mut held, backup(2, 6, vec![1]), 1);
+        assert_eq!(held_for(&held, 2), Some((6, vec![1], false)));
+    }
+
+    #[test]
+    fn own_orders_and_unknown_owners_are_not_held() {
+        let mut held = HashMap::new();
+        hold(&mut held, backup(1, 3, vec![2]), 1);
+        hold(&mut held, CabBackupMessage::Request { owner: 2 }, 1);
+        hold(&mut held, CabBackupMessage::Confirm { owner: 2, version: 1 }, 1);
+        assert!(held.is_empty());
+    }
+
+    #[test]
+    fn backup_is_only_restored_if_it_is_newer() {
+        let (info, _) = step(&ElevatorInfo::idle_at(1, 0), Event::OnNewOrder { btn: CallButton { floor: 2, call: CAB } });
+        assert_eq!(info.cab_version, 1);
+        assert_eq!(cab_floors(&info), vec![2]);
+
+        let same = CabBackup { version: 1, floors: vec![2] };
+        let newer = CabBackup { version: 2, floors: vec![2, 3] };
+        assert!(!same.is_newer_than(info.cab_version));
+        assert!(newer.is_newer_than(info.cab_version));
+
+        // Taking the orders back must not make our own version look older than the backup
+        let (restored, _) = step(&info, Event::OnCabBackupRestored { version: newer.version });
+        let (restored, _) = step(&restored, Event::OnNewOrder { btn: newer.orders()[1] });
+        assert!(!newer.is_newer_than(restored.cab_version));
+    }
+}
@@ -1,98 +1,370 @@
-use crossbeam_channel as cbc;
+//! Which elevator should take a hall order. What "best" means is decided by the `DispatchStrategy` picked with
+//! `dispatch_strategy` in the config, so different strategies can be compared on the same building:
+//!
+//! * `time_to_idle` - how long until the car is done with all its orders, the new one included
+//! * `nearest_car` - how far the car is from the floor of the order right now
+//! * `estimated_waiting_time` - how long the passenger of the new order has to wait
+//! * `sum_of_waiting_times` - how much longer all the passengers of the car wait in total, the new one included
+//! * `hall_request_assigner` - the assigner from the course, see `hall_request_assigner.rs`
+//!
+//! Whatever the strategy, a full car, or one that can not move or is out of service, never gets an order
+//! if another car can take it, and nearly full cars get a penalty. The orders of a failed car are always taken
+//! over by time to idle, see `rank_for_takeover`.
+use serde;
 
 use crate::local_elevator::elevio::{elev, poll};
-use crate::local_elevator::fsm::door_timer::TimerCommand;
 use crate::local_elevator::fsm::elevatorfsm::*;
 use crate::local_elevator::elevio::poll::CallButton;
 use crate::global_elevator_info::connected_elevators::ConnectedElevatorInfo;
-use crate::util::constants::ID as LOCAL_ID;
-use crate::util::constants as setting;
+use crate::global_elevator_info::failure_detector::PeerHealth;
+use crate::order_assigner::hall_request_assigner::HallRequestAssigner;
+use crate::util::config::Config;
 
 const TRAVEL_TIME: u64 = 2;
+/// Added to the cost of a car just below full load, less for cars with more room
+const NEARLY_FULL_PENALTY: u64 = 10;
 
+/// A way to tell how well suited an elevator is for a hall order
+pub trait DispatchStrategy {
+    /// The cost for `elev_info` to take `button`, lower is better. `elev_info` is known to be able to take it.
+    fn cost(&self, elev_info: &ElevatorInfo, button: CallButton, config: &Config) -> usize;
 
-pub fn find_lowest_cost_id(
-    connected_elevator_info: ConnectedElevatorInfo, 
-    button_to_add: CallButton) -> usize {
-    
-    let local_elev_info;
-    match connected_elevator_info.get_local_elevator_info() {
-        Some(v) => local_elev_info = v,
-        None => {
-            println!("Info not found at ID {}, assigning order to local elevator.", LOCAL_ID);
-            return LOCAL_ID;
-    }
-}
-
-    let mut lowest_cost: usize =
-        time_to_idle(local_elev_info, button_to_add);
-    let mut lowest_cost_id: usize = LOCAL_ID;
-
-    for i in connected_elevator_info.get_connected_elevators().iter().cloned() {
-        let elev_cost: usize;
-        match i.clone() {
-            Some(val) => {
-                elev_cost = time_to_idle((val).clone(), button_to_add);
-                if elev_cost < lowest_cost {
-                    lowest_cost_id = (val).id;
-                    lowest_cost = elev_cost;
-                }
+    /// For strategies that assign all the hall orders at once instead of giving each car a cost: the id of the car
+    /// in `elevators` that should take `button`. It is ranked first, and the others by `cost` in case it does not answer.
+    fn choose(&self, _elevators: &[ElevatorInfo], _button: CallButton, _config: &Config) -> Option<usize> {
+        return None;
+    }
+}
+
+/// The built in strategies, as named in the config
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum StrategyKind {
+    TimeToIdle,
+    NearestCar,
+    EstimatedWaitingTime,
+    SumOfWaitingTimes,
+    /// The assigner from the course, see `hall_request_assigner.rs`
+    HallRequestAssigner,
+}
+
+impl StrategyKind {
+    pub fn strategy(self) -> Box<dyn DispatchStrategy> {
+        match self {
+            StrategyKind::TimeToIdle => Box::new(TimeToIdle),
+            StrategyKind::NearestCar => Box::new(NearestCar),
+            StrategyKind::EstimatedWaitingTime => Box::new(EstimatedWaitingTime),
+            StrategyKind::SumOfWaitingTimes => Box::new(SumOfWaitingTimes),
+            StrategyKind::HallRequestAssigner => Box::new(HallRequestAssigner),
+        }
+    }
+}
+
+pub struct TimeToIdle;
+pub struct NearestCar;
+pub struct EstimatedWaitingTime;
+pub struct SumOfWaitingTimes;
+
+impl DispatchStrategy for TimeToIdle {
+    fn cost(&self, elev_info: &ElevatorInfo, button: CallButton, config: &Config) -> usize {
+        let mut duration = 0;
+        simulate(with_order(elev_info, button), config, |step_duration, _| duration += step_duration);
+        return duration;
+    }
+}
+
+impl DispatchStrategy for NearestCar {
+    fn cost(&self, elev_info: &ElevatorInfo, button: CallButton, _config: &Config) -> usize {
+        return usize::from(elev_info.floor.abs_diff(button.floor)) * TRAVEL_TIME as usize;
+    }
+}
+
+impl DispatchStrategy for EstimatedWaitingTime {
+    fn cost(&self, elev_info: &ElevatorInfo, button: CallButton, config: &Config) -> usize {
+        let info = with_order(elev_info, button);
+        let mut waited = 0;
+        let mut served = !info.responsible_orders.is_active(button);
+        simulate(info, config, |step_duration, after| {
+            if !served {
+                waited += step_duration;
+                served = !after.responsible_orders.is_active(button);
             }
-            None => {}
+        });
+        return waited;
+    }
+}
+
+impl DispatchStrategy for SumOfWaitingTimes {
+    fn cost(&self, elev_info: &ElevatorInfo, button: CallButton, config: &Config) -> usize {
+        let total_wait = |info: ElevatorInfo| {
+            let mut waiting = count_orders(&info);
+            let mut total = 0;
+            simulate(info, config, |step_duration, after| {
+                total += step_duration * waiting;
+                waiting = count_orders(after);
+            });
+            return total;
+        };
+        return total_wait(with_order(elev_info, button)).saturating_sub(total_wait(elev_info.clone()));
+    }
+}
+
+/// Ranks the elevators that serve the floor of the order, the one that can take it the fastest first.
+/// Suspected elevators come after all the others, as they may be gone. On equal cost the local elevator comes first,
+/// so we do not send orders over the network for nothing, and then the lowest id. The list is empty if no connected
+/// elevator serves the floor.
+pub fn rank_candidates(
+    connected_elevator_info: &ConnectedElevatorInfo,
+    button_to_add: CallButton,
+    config: &Config) -> Vec<usize> {
+
+    let local_id = connected_elevator_info.get_local_id();
+    if connected_elevator_info.get_local_elevator_info().is_none() {
+        println!("Info not found at ID {}, assigning order to local elevator.", local_id);
+        return vec![local_id];
+    }
+
+    let strategy = config.dispatch_strategy.strategy();
+    let mut costs: Vec<(bool, usize, bool, usize)> = connected_elevator_info.get_connected_elevators().iter()
+        .filter(|elev| elev.serves(button_to_add.floor))
+        .map(|elev| {
+            let suspect = connected_elevator_info.get_peer_health(elev.id) == PeerHealth::Suspect;
+            (suspect, cost(strategy.as_ref(), elev, &button_to_add, config), elev.id != local_id, elev.id)
+        })
+        .collect();
+    costs.sort();
+    let mut ranking: Vec<usize> = costs.iter().map(|&(_, _, _, id)| id).collect();
+    let able: Vec<usize> = costs.iter().filter(|&&(suspect, cost, _, _)| !suspect && cost != usize::MAX).map(|&(_, _, _, id)| id).collect();
+    put_chosen_first(&mut ranking, strategy.as_ref(), connected_elevator_info, &able, button_to_add, config);
+    return ranking;
+}
+
+/// Ranks the elevators that serve the floor of an order whose elevator failed, for `takeover.rs`.
+/// Every node must get the same list, so only the time to idle and then the id count, not who is asking,
+/// how healthy it looks from here, or the answer of a `hall_request_assigner` that may time out on some nodes only.
+/// The elevators in `failed` are left out, and so are those that can not take the order now, e.g. because they
+/// are full or stuck, as their fsm would drop it. The list is empty if nobody can take it.
+pub fn rank_for_takeover(
+    connected_elevator_info: &ConnectedElevatorInfo,
+    button: CallButton,
+    failed: &[usize],
+    config: &Config) -> Vec<usize> {
+
+    let mut costs: Vec<(usize, usize)> = connected_elevator_info.get_connected_elevators().iter()
+        .filter(|elev| elev.serves(button.floor) && !failed.contains(&elev.id))
+        .map(|elev| (cost(&TimeToIdle, elev, &button, config), elev.id))
+        .filter(|&(cost, _)| cost != usize::MAX)
+        .collect();
+    costs.sort();
+    return costs.iter().map(|&(_, id)| id).collect();
+}
+
+/// Moves the car the strategy chooses among those in `able` to the front of `ranking`, if it chooses one
+fn put_chosen_first(
+    ranking: &mut Vec<usize>,
+    strategy: &dyn DispatchStrategy,
+    connected_elevator_info: &ConnectedElevatorInfo,
+    able: &[usize],
+    button: CallButton,
+    config: &Config) {
+
+    let elevators: Vec<ElevatorInfo> = connected_elevator_info.get_connected_elevators().into_iter()
+        .filter(|elev| able.contains(&elev.id))
+        .collect();
+    if elevators.is_empty() {
+        return;
+    }
+    if let Some(chosen) = strategy.choose(&elevators, button, config) {
+        if let Some(position) = ranking.iter().position(|&id| id == chosen) {
+            let id = ranking.remove(position);
+            ranking.insert(0, id);
         }
     }
-    return lowest_cost_id;
 }
 
-/// Calculates the time it takes for an elevator to reach the idle
-/// state after we have added the new order.
-///
-/// `fsm` - elevator to simulate
-///
-/// `button` - Button corresponding to order we want to add.
-fn time_to_idle(ref mut elev_info: ElevatorInfo, ref button: poll::CallButton) -> usize {
-    // Dummy timers needed to "disconnect" the elevator from its current channel
-    let (dummy_hw_tx, _dummy_hw_rx) = cbc::unbounded::<elev::HardwareCommand>();
-    let (dummy_timer_tx, _dummy_timer_rx) = cbc::unbounded::<TimerCommand>();
-    let (dummy_state_updater_tx, __dummy_state_updater_rx) = cbc::unbounded::<State>();
-
-    let mut elev = Elevator::create_simulation_elevator(elev_info.clone(), dummy_hw_tx, dummy_timer_tx, dummy_state_updater_tx);
-    elev.on_event(Event::OnNewOrder{btn: *button});
-    let mut duration: usize = 0;
-    let state = elev.get_state();
-    if state == State::Obstructed || state == State::ObstrTimedOut || 
-    state == State::MovTimedOut || state == State::Initializing {
+/// Another car that is better suited for our hall order `button` than we are, by more than `reassign_hysteresis`,
+/// so orders do not flap back and forth between cars that are about as good. Only connected cars are considered.
+/// Only the owner of an order asks, so an order is never moved by two nodes at once.
+pub fn reassignment_target(connected_elevator_info: &ConnectedElevatorInfo, button: CallButton, config: &Config) -> Option<usize> {
+    let strategy = config.dispatch_strategy.strategy();
+    let local = connected_elevator_info.get_local_elevator_info()?;
+    let own_cost = cost(strategy.as_ref(), &local, &button, config);
+    let (best_cost, best_id) = connected_elevator_info.get_connected_elevators().iter()
+        .filter(|elev| elev.id != local.id && elev.serves(button.floor)
+            && connected_elevator_info.get_peer_health(elev.id) == PeerHealth::Connected)
+        .map(|elev| (cost(strategy.as_ref(), elev, &button, config), elev.id))
+        .min()?;
+    if best_cost.saturating_add(config.reassign_hysteresis as usize) < own_cost {
+        return Some(best_id);
+    }
+    return None;
+}
+
+/// The cost given by the strategy, with a penalty for the load of the car. A full car would pass the order,
+/// and a car that can not move or is out of service would not get to it, so they get the highest cost.
+pub(crate) fn cost(strategy: &dyn DispatchStrategy, elev_info: &ElevatorInfo, button: &poll::CallButton, config: &Config) -> usize {
+    if elev_info.is_full() || !can_take_orders(elev_info) {
         return usize::MAX;
     }
-    while elev.get_state() != State::Idle {
-        duration += simulate_next_step(&mut elev);
+    let load_penalty = NEARLY_FULL_PENALTY * u64::from(elev_info.load) / u64::from(elev_info.full_load_percent.max(1));
+    return strategy.cost(elev_info, *button, config).saturating_add(load_penalty as usize);
+}
+
+fn can_take_orders(elev_info: &ElevatorInfo) -> bool {
+    let state = elev_info.state;
+    return !(state == State::Obstructed || state == State::ObstrTimedOut ||
+    state == State::MovTimedOut || state == State::Initializing || matches!(state, State::EmergencyStop { .. })
+    || elev_info.recall != Recall::Off);
+}
+
+/// The elevator as it would be right after getting the order.
+/// A parking elevator gives up its trip to the home floor when it gets the order,
+/// so it is simulated as an idle elevator that happens to be moving.
+fn with_order(elev_info: &ElevatorInfo, button: CallButton) -> ElevatorInfo {
+    return step(elev_info, Event::OnNewOrder{btn: button}).0;
+}
+
+fn count_orders(info: &ElevatorInfo) -> usize {
+    let mut count = 0;
+    for floor in 0..info.responsible_orders.inside_queue.len() as u8 {
+        for call in 0..=poll::CAB {
+            if info.responsible_orders.is_active(CallButton{floor, call}) {
+                count += 1;
+            }
+        }
+    }
+    return count;
+}
+
+/// Runs the elevator until it stops and has nothing more to do. After every step, `visit` gets how long the step took and the elevator after it.
+///
+/// `info` - elevator to simulate
+///
+/// `config` - Settings used for estimating the time spent with the door open
+fn simulate<F: FnMut(usize, &ElevatorInfo)>(mut info: ElevatorInfo, config: &Config, mut visit: F) {
+    // A parking elevator has no orders, so it is done already
+    while matches!(info.state, State::Moving | State::DoorOpen) {
+        let duration = simulate_next_step(&mut info, config);
+        visit(duration, &info);
     }
-    return duration;
 }
 
 /// Estimates the time it takes for the elevator to reach the next event.
 /// It is to be used in the loop of the cost function.
 ///
-/// `fsm` - the fsm to simulate
-fn simulate_next_step(fsm: &mut Elevator) -> usize {
-    match fsm.get_state() {
+/// `info` - the elevator to simulate
+///
+/// `config` - Settings used for estimating the time spent with the door open
+fn simulate_next_step(info: &mut ElevatorInfo, config: &Config) -> usize {
+    match info.state {
         State::Moving => {
-            if fsm.get_dirn() == elev::DIRN_DOWN {
-                fsm.on_event(Event::OnFloorArrival {
-                    floor: (fsm.get_floor() - 1),
-                });
+            // A car that turned around between floors may be heading back to the floor it left
+            let top_floor = info.responsible_orders.inside_queue.len() as u8 - 1;
+            let floor = if info.dirn == elev::DIRN_DOWN {
+                info.floor.saturating_sub(1)
             } else {
-                fsm.on_event(Event::OnFloorArrival {
-                    floor: (fsm.get_floor() + 1),
-                });
-            }
+                (info.floor + 1).min(top_floor)
+            };
+            *info = step(info, Event::OnFloorArrival { floor }).0;
             return TRAVEL_TIME as usize;
         }
         State::DoorOpen => {
-            fsm.on_event(Event::OnDoorTimeOut);
-            return setting::DOOR_OPEN_TIME as usize;
+            *info = step(info, Event::OnDoorTimeOut).0;
+            return config.door_open_time as usize;
         }
         State::Idle => 0,
         _ => 0,
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UP_AT_2: CallButton = CallButton { floor: 2, call: poll::HALL_UP };
+
+    /// We are full, and the only other car is `other`
+    fn full_car_and(other: ElevatorInfo) -> (ConnectedElevatorInfo, Config) {
+        let config = Config { id: Some(1), ..Default::default() };
+        let mut full = ElevatorInfo::idle_at(1, 0);
+        full.load = 100;
+        let mut info = ConnectedElevatorInfo::new(full, &config);
+        info.update_remote_elevator_info(vec![other]);
+        return (info, config);
+    }
+
+    /// On its way from 0 to a cab order at 3, and the order is on the way
+    fn going_up_from_0_to_3() -> ElevatorInfo {
+        return step(&ElevatorInfo::idle_at(1, 0), Event::OnNewOrder { btn: CallButton { floor: 3, call: poll::CAB } }).0;
+    }
+
+    #[test]
+    fn every_strategy_counts_what_it_says() {
+        let config = Config { door_open_time: 3, ..Default::default() };
+        let car = going_up_from_0_to_3();
+        let up_at_1 = CallButton { floor: 1, call: poll::HALL_UP };
+        // One floor away
+        assert_eq!(cost(&NearestCar, &car, &up_at_1, &config), 2);
+        // Served when the door closes at the first stop
+        assert_eq!(cost(&EstimatedWaitingTime, &car, &up_at_1, &config), 2 + 3);
+        // Three floors and two stops
+        assert_eq!(cost(&TimeToIdle, &car, &up_at_1, &config), 3 * 2 + 2 * 3);
+        // The new passenger waits as above, and the one inside waits for the extra stop
+        assert_eq!(cost(&SumOfWaitingTimes, &car, &up_at_1, &config), (2 + 3) + 3);
+    }
+
+    #[test]
+    fn nearly_full_car_costs_more_and_full_car_costs_the_most() {
+        let config = Config::default();
+        let mut car = ElevatorInfo::idle_at(1, 0);
+        let empty = cost(&NearestCar, &car, &UP_AT_2, &config);
+        car.load = car.full_load_percent / 2;
+        assert_eq!(cost(&NearestCar, &car, &UP_AT_2, &config), empty + NEARLY_FULL_PENALTY as usize / 2);
+        car.load = car.full_load_percent;
+        assert_eq!(cost(&NearestCar, &car, &UP_AT_2, &config), usize::MAX);
+    }
+
+    #[test]
+    fn order_is_only_moved_to_a_car_better_by_more_than_the_hysteresis() {
+        // We are 2 floors from the order, the other car 1 floor, so it is better by one floor of travel
+        let reassign = |reassign_hysteresis| {
+            let config = Config { id: Some(1), dispatch_strategy: StrategyKind::NearestCar, reassign_hysteresis, ..Default::default() };
+            let mut info = ConnectedElevatorInfo::new(ElevatorInfo::idle_at(1, 0), &config);
+            info.update_remote_elevator_info(vec![ElevatorInfo::idle_at(2, 3)]);
+            return reassignment_target(&info, UP_AT_2, &config);
+        };
+        assert_eq!(reassign(0), Some(2));
+        assert_eq!(reassign(TRAVEL_TIME - 1), Some(2));
+        assert_eq!(reassign(TRAVEL_TIME), None);
+        assert_eq!(reassign(Config::default().reassign_hysteresis), None);
+    }
+
+    #[test]
+    fn released_order_goes_to_a_car_that_can_take_it() {
+        let (info, config) = full_car_and(ElevatorInfo::idle_at(2, 3));
+        assert_eq!(rank_for_takeover(&info, UP_AT_2, &[1], &config), vec![2]);
+    }
+
+    #[test]
+    fn released_order_is_kept_when_the_only_other_car_can_not_move() {
+        let mut stuck = ElevatorInfo::idle_at(2, 3);
+        stuck.state = State::MovTimedOut;
+        let (info, config) = full_car_and(stuck);
+        assert!(rank_for_takeover(&info, UP_AT_2, &[1], &config).is_empty());
+    }
+
+    #[test]
+    fn takeover_ranks_by_time_to_idle_whatever_the_strategy() {
+        let config = Config { id: Some(1), dispatch_strategy: StrategyKind::NearestCar, ..Default::default() };
+        // Next to the order, but with a long trip ahead of it
+        let mut busy = ElevatorInfo::idle_at(2, 2);
+        for floor in [0, 3].iter().copied() {
+            busy = step(&busy, Event::OnNewOrder { btn: CallButton { floor, call: poll::CAB } }).0;
+        }
+        let mut info = ConnectedElevatorInfo::new(ElevatorInfo::idle_at(1, 0), &config);
+        info.update_remote_elevator_info(vec![busy, ElevatorInfo::idle_at(3, 0)]);
+
+        assert_eq!(rank_candidates(&info, UP_AT_2, &config)[0], 2);
+        assert_eq!(rank_for_takeover(&info, UP_AT_2, &[3], &config), vec![1, 2]);
+    }
+}
@@ -0,0 +1,241 @@
+//! A `DispatchStrategy` that uses the `hall_request_assigner` from the course, so our own cost functions can be
+//! compared with the reference one. The assigner takes the hall orders of the building and the state of every car,
+//!
+//! ```text
+//! {"hallRequests": [[up, down], ...], "states": {"1": {"behaviour": "moving", "floor": 2, "direction": "up", "cabRequests": [...]}}}
+//! ```
+//!
+//! and answers with the hall orders each car should take, `{"1": [[up, down], ...], ...}`.
+//!
+//! With `hall_request_assigner_path` set, that executable is run for every hall call. Otherwise the assignment is
+//! done in-process with the same input and output: every hall order in turn, from the bottom floor and up before down,
+//! goes to the car that is idle the soonest with it, given the orders it got before, with the same load penalty and
+//! the same cars left out as for the other strategies. If the executable fails, or has not answered within
+//! `hall_request_assigner_timeout_millisec`, it is killed and the cars are ranked by time to idle.
+//!
+//! Only new hall calls go through the assigner. The takeover of orphaned orders ranks the cars by time to idle
+//! whatever the strategy, as every node must get the same ranking, and the reassignment of orders compares the
+//! time to idle of the cars as well.
+use serde;
+use std::collections::BTreeMap;
+use std::io;
+use std::process;
+use std::thread;
+use std::time;
+
+use crate::local_elevator::elevio::elev;
+use crate::local_elevator::elevio::poll::{CallButton, CAB};
+use crate::local_elevator::fsm::elevatorfsm::{step, ClearPolicy, ElevatorInfo, Event, State};
+use crate::order_assigner::cost_function::{self, DispatchStrategy, TimeToIdle};
+use crate::util::config::Config;
+
+/// The travel time between two floors the cost functions assume, in milliseconds
+const TRAVEL_DURATION_MILLISEC: u64 = 2000;
+/// How often we look for the executable to be done
+const POLL_PERIOD_MILLISEC: u64 = 5;
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AssignerInput {
+    hall_requests: Vec<[bool; 2]>,
+    states: BTreeMap<String, AssignerState>,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AssignerState {
+    behaviour: &'static str,
+    floor: u8,
+    direction: &'static str,
+    cab_requests: Vec<bool>,
+}
+
+/// The hall orders given to each car, by id
+type AssignerOutput = BTreeMap<String, Vec<[bool; 2]>>;
+
+pub struct HallRequestAssigner;
+
+impl DispatchStrategy for HallRequestAssigner {
+    fn cost(&self, elev_info: &ElevatorInfo, button: CallButton, config: &Config) -> usize {
+        return TimeToIdle.cost(elev_info, button, config);
+    }
+
+    fn choose(&self, elevators: &[ElevatorInfo], button: CallButton, config: &Config) -> Option<usize> {
+        let hall_requests = hall_requests(elevators, button);
+        let output = if config.hall_request_assigner_path.is_empty() {
+            assign_in_process(elevators, &hall_requests, config)
+        } else {
+            match run_executable(&to_input(elevators, hall_requests), config) {
+                Ok(output) => output,
+                Err(err) => {
+                    println!("hall_request_assigner failed, ranking by time to idle instead: {}", err);
+                    return None;
+                }
+            }
+        };
+        let call = usize::from(button.call);
+        return output.iter()
+            .find(|(_, requests)| requests.get(usize::from(button.floor)).is_some_and(|request| request[call]))
+            .and_then(|(id, _)| id.parse().ok());
+    }
+}
+
+/// The hall orders the cars have now, and the new one
+fn hall_requests(elevators: &[ElevatorInfo], button: CallButton) -> Vec<[bool; 2]> {
+    let num_floors = elevators[0].responsible_orders.inside_queue.len();
+    let mut requests = vec![[false; 2]; num_floors];
+    for elev in elevators {
+        for (floor, request) in requests.iter_mut().enumerate() {
+            for call in 0..CAB {
+                if elev.responsible_orders.is_active(CallButton { floor: floor as u8, call }) {
+                    request[usize::from(call)] = true;
+                }
+            }
+        }
+    }
+    requests[usize::from(button.floor)][usize::from(button.call)] = true;
+    return requests;
+}
+
+fn to_input(elevators: &[ElevatorInfo], hall_requests: Vec<[bool; 2]>) -> AssignerInput {
+    let states = elevators.iter().map(|elev| {
+        let behaviour = match elev.state {
+            State::Moving => "moving",
+            State::DoorOpen | State::Obstructed => "doorOpen",
+            _ => "idle",
+        };
+        let direction = match (elev.state, elev.dirn) {
+            // A parking car drops its trip when it gets an order
+            (State::Parking { .. }, _) => "stop",
+            (_, elev::DIRN_UP) => "up",
+            (_, elev::DIRN_DOWN) => "down",
+            _ => "stop",
+        };
+        let cab_requests = (0..elev.responsible_orders.inside_queue.len() as u8)
+            .map(|floor| elev.responsible_orders.is_active(CallButton { floor, call: CAB }))
+            .collect();
+        (elev.id.to_string(), AssignerState { behaviour, floor: elev.floor, direction, cab_requests })
+    }).collect();
+    return AssignerInput { hall_requests, states };
+}
+
+fn run_executable(input: &AssignerInput, config: &Config) -> io::Result<AssignerOutput> {
+    let input = serde_json::to_string(input).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let clear_request_type = match config.order_clearing {
+        ClearPolicy::All => "all",
+        ClearPolicy::InDirection => "inDirn",
+    };
+    let mut child = process::Command::new(&config.hall_request_assigner_path)
+        .arg("--input").arg(&input)
+        .arg("--travelDuration").arg(TRAVEL_DURATION_MILLISEC.to_string())
+        .arg("--doorOpenDuration").arg((config.door_open_time * 1000).to_string())
+        .arg("--clearRequestType").arg(clear_request_type)
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()?;
+    // The passenger is waiting, so a hung executable must not hold up the hall call
+    let deadline = time::Instant::now() + config.hall_request_assigner_timeout();
+    while child.try_wait()?.is_none() {
+        if time::Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(io::ErrorKind::TimedOut,
+                format!("no answer within {} ms", config.hall_request_assigner_timeout_millisec)));
+        }
+        thread::sleep(time::Duration::from_millis(POLL_PERIOD_MILLISEC));
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("exited with {}: {}",
+            output.status, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    return serde_json::from_slice(&output.stdout).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+}
+
+fn assign_in_process(elevators: &[ElevatorInfo], hall_requests: &[[bool; 2]], config: &Config) -> AssignerOutput {
+    // Every car starts with only its cab orders, and gets the hall orders one by one
+    let mut cars: Vec<ElevatorInfo> = elevators.iter().map(|elev| {
+        let mut car = elev.clone();
+        for floor in 0..hall_requests.len() as u8 {
+            for call in 0..CAB {
+                car.responsible_orders.remove_order(CallButton { floor, call });
+            }
+        }
+        return car;
+    }).collect();
+    let mut output: AssignerOutput = cars.iter()
+        .map(|car| (car.id.to_string(), vec![[false; 2]; hall_requests.len()]))
+        .collect();
+
+    for (floor, request) in hall_requests.iter().enumerate() {
+        for call in 0..CAB {
+            if !request[usize::from(call)] {
+                continue;
+            }
+            let button = CallButton { floor: floor as u8, call };
+            let best = cars.iter_mut()
+                .filter(|car| car.serves(button.floor))
+                .map(|car| (cost_function::cost(&TimeToIdle, car, &button, config), car))
+                .filter(|(cost, _)| *cost != usize::MAX)
+                .min_by_key(|(cost, car)| (*cost, car.id))
+                .map(|(_, car)| car);
+            if let Some(car) = best {
+                *car = step(car, Event::OnNewOrder { btn: button }).0;
+                output.get_mut(&car.id.to_string()).unwrap()[floor][usize::from(call)] = true;
+            }
+        }
+    }
+    return output;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_and_stopped_cars_get_nothing_in_process() {
+        let config = Config { id: Some(1), ..Default::default() };
+        let button = CallButton { floor: 2, call: 0 };
+        let mut full = ElevatorInfo::idle_at(1, 2);
+        full.load = 100;
+        let mut stopped = ElevatorInfo::idle_at(2, 2);
+        stopped.state = State::EmergencyStop { door_open: true, parking: None };
+        let elevators = [full, stopped, ElevatorInfo::idle_at(3, 0)];
+        assert_eq!(HallRequestAssigner.choose(&elevators, button, &config), Some(3));
+    }
+
+    #[test]
+    fn nearly_full_car_is_passed_for_an_empty_one() {
+        let config = Config { id: Some(1), ..Default::default() };
+        let button = CallButton { floor: 2, call: 0 };
+        let mut loaded = ElevatorInfo::idle_at(1, 1);
+        loaded.load = config.full_load_percent - 1;
+        let elevators = [loaded, ElevatorInfo::idle_at(2, 3)];
+        assert_eq!(HallRequestAssigner.choose(&elevators, button, &config), Some(2));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hung_executable_is_killed() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("hung-assigner-{}", process::id()));
+        std::fs::write(&path, "#!/bin/sh\nexec sleep 10\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let config = Config {
+            id: Some(1),
+            hall_request_assigner_path: path.to_string_lossy().into_owned(),
+            hall_request_assigner_timeout_millisec: 100,
+            ..Default::default()
+        };
+        let elevators = [ElevatorInfo::idle_at(1, 0)];
+        let button = CallButton { floor: 2, call: 0 };
+
+        let start = time::Instant::now();
+        let result = run_executable(&to_input(&elevators, hall_requests(&elevators, button)), &config);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < time::Duration::from_secs(5));
+    }
+}
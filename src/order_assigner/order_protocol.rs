@@ -0,0 +1,81 @@
+//! The messages used for handing hall orders to other elevators on `order_port`, and the events they give for logging.
+//!
+//! The elevator whose button was pressed sends `Assign` to the best fit elevator, which answers with `Ack` once its
+//! fsm has taken the order, or `Nack` if the fsm turned it down, e.g. because the car can not move or is in recall.
+//! Without an answer, the assign is sent again with a growing delay, and after `assign_retries`
+//! retries the next-best elevator is tried. On `Nack` it is tried right away. See `order_transmitter.rs`.
+//!
+//! The same messages are used when a node moves one of its own hall orders to a better car, see `reassign_period_sec`.
+//! The order is only dropped on `Ack`, so it always belongs to at least one car.
+use serde;
+use std::time;
+
+use crate::local_elevator::elevio::poll::CallButton;
+
+/// Identifies one hall order from the press of the button until it is acknowledged.
+/// `seq` starts from the clock when the node starts, so ids are not reused after a restart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct OrderId {
+    pub origin: usize,
+    pub seq: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum OrderMessage {
+    Assign { order_id: OrderId, to: usize, button: CallButton },
+    Ack { order_id: OrderId, from: usize },
+    Nack { order_id: OrderId, from: usize },
+}
+
+/// Everything that happens to an order on its way to an elevator
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum OrderEvent {
+    /// `attempt` counts from 0 for every elevator we try
+    AssignSent { order_id: OrderId, to: usize, button: CallButton, attempt: u32 },
+    AssignReceived { order_id: OrderId, button: CallButton },
+    AckSent { order_id: OrderId },
+    AckReceived { order_id: OrderId, from: usize },
+    /// Our fsm did not take the order
+    NackSent { order_id: OrderId },
+    NackReceived { order_id: OrderId, from: usize },
+    /// `from` did not answer, so we try `to` instead
+    FallBack { order_id: OrderId, from: usize, to: usize },
+    AssignedLocally { order_id: OrderId, button: CallButton },
+    /// Our order was moved to `to`, a better fit, and we have dropped it
+    HandedOver { order_id: OrderId, to: usize, button: CallButton },
+    /// `to` did not answer, so we keep the order we tried to move
+    Kept { order_id: OrderId, button: CallButton },
+    /// Every elevator was tried without an ack, and we do not serve the floor ourselves
+    Undelivered { order_id: OrderId, button: CallButton },
+}
+
+/// Hands out the ids for the orders from this node
+pub struct OrderIds {
+    origin: usize,
+    next_seq: u64,
+}
+
+impl OrderIds {
+    pub fn new(origin: usize) -> OrderIds {
+        let since_epoch = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap_or_default();
+        OrderIds {
+            origin,
+            next_seq: since_epoch.as_micros() as u64,
+        }
+    }
+
+    pub fn next_id(&mut self) -> OrderId {
+        self.next_seq += 1;
+        return OrderId { origin: self.origin, seq: self.next_seq };
+    }
+}
+
+/// Prints the events as JSON, one per line, so they are easy to pick out of the log
+pub fn log_events(events: crossbeam_channel::Receiver<OrderEvent>) {
+    for event in events.iter() {
+        if let Ok(line) = serde_json::to_string(&event) {
+            println!("{}", line);
+        }
+    }
+}
@@ -1,59 +1,58 @@
 use std::thread::*;
-use std::time;
 use crossbeam_channel as cbc;
 
-use crate::local_elevator::elevio::poll::{CallButton, CAB};
-use crate::local_elevator::fsm::elevatorfsm::ElevatorInfo;
-use crate::util::constants as setting;
-
-
-pub fn hall_order_receiver(assign_orders_locally_tx: cbc::Sender<CallButton>) {
+use crate::local_elevator::elevio::poll::CallButton;
+use crate::order_assigner::order_protocol::{OrderEvent, OrderId, OrderMessage};
+use crate::util::config::Config;
+
+
+/// Takes the hall orders other elevators assign to us, and answers them.
+/// * `assigned_tx` passes the orders on to the fsm
+/// * `accepted_rx` says if the fsm took the order, so we answer with `Ack` or `Nack`
+/// * `replies_tx` passes the answers to the orders we sent ourselves on to `hall_order_transmitter`
+pub fn hall_order_receiver(
+    assigned_tx: cbc::Sender<(OrderId, CallButton)>,
+    accepted_rx: cbc::Receiver<(OrderId, bool)>,
+    replies_tx: cbc::Sender<OrderMessage>,
+    events: cbc::Sender<OrderEvent>,
+    config: &Config) {
     // The reciever for orders
-    let (order_recv_tx, order_recv_rx) = cbc::unbounded::<(usize, CallButton)>();
+    let (order_recv_tx, order_recv_rx) = cbc::unbounded::<OrderMessage>();
+    let (send_bcast_tx, send_bcast_rx) = cbc::unbounded::<OrderMessage>();
+    let port = config.order_port;
     spawn(move || {
-        crate::network_interface::bcast::rx(setting::ORDER_PORT, order_recv_tx);
+        crate::network_interface::bcast::rx(port, order_recv_tx);
     });
-
-    loop {
-        let res = order_recv_rx.recv();
-        let order = res.unwrap();
-        let id = order.0;
-        let call_button = order.1;
-        if id == setting::ID {
-            assign_orders_locally_tx.send(call_button).unwrap();
-        }
-    }
-}
-
-pub fn cab_order_backup_rx<T: serde::de::DeserializeOwned>(assign_cab_orders_locally_tx: cbc::Sender::<CallButton>) {
-
-    let start_time = time::Instant::now();
-    let timeout = time::Duration::from_millis(500);
-
-    let (cab_backup_recv_tx, cab_backup_recv_rx) = cbc::unbounded::<ElevatorInfo>();
     spawn(move || {
-        crate::network_interface::bcast::rx(setting::CAB_BACKUP_PORT, cab_backup_recv_tx);
+        crate::network_interface::bcast::tx(port, send_bcast_rx, 1);
     });
 
-    while time::Instant::now().duration_since(start_time)<time::Duration::from_secs(5){
-        let r = cab_backup_recv_rx.recv_timeout(timeout);
-         match r {
-            Ok(val) => {
-                let elev_info = val.clone();
-                let id = elev_info.clone().id;
-
-                if id == setting::ID{
-                    for f in 0..setting::ELEV_NUM_FLOORS {
-                        let btn = CallButton{floor: f, call: CAB};
-                        if elev_info.responsible_orders.is_active(btn) {
-                            assign_cab_orders_locally_tx.send(btn).unwrap();
-                        }
-                    }
+    loop {
+        cbc::select! {
+            recv(order_recv_rx) -> a => {
+                let message = a.unwrap();
+                match message {
+                    // A retried assign is answered again, the fsm does not mind getting the same order twice
+                    OrderMessage::Assign { order_id, to, button } if to == config.id() => {
+                        events.send(OrderEvent::AssignReceived { order_id, button }).unwrap();
+                        assigned_tx.send((order_id, button)).unwrap();
+                    },
+                    OrderMessage::Ack { order_id, .. } | OrderMessage::Nack { order_id, .. } if order_id.origin == config.id() => {
+                        replies_tx.send(message).unwrap();
+                    },
+                    _ => {},
                 }
-
-            }
-            Err(_) => {},
+            },
+            recv(accepted_rx) -> a => {
+                let (order_id, accepted) = a.unwrap();
+                if accepted {
+                    send_bcast_tx.send(OrderMessage::Ack { order_id, from: config.id() }).unwrap();
+                    events.send(OrderEvent::AckSent { order_id }).unwrap();
+                } else {
+                    send_bcast_tx.send(OrderMessage::Nack { order_id, from: config.id() }).unwrap();
+                    events.send(OrderEvent::NackSent { order_id }).unwrap();
+                }
+            },
         }
     }
-
 }
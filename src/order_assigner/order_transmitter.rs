@@ -1,37 +1,110 @@
 use crossbeam_channel as cbc;
+use std::collections::HashMap;
 use std::thread::*;
+use std::time;
 
-use crate::util::constants as setting;
+use crate::util::config::Config;
 use crate::global_elevator_info::connected_elevators::ConnectedElevatorInfo;
 use crate::local_elevator::elevio::poll::{CallButton, CAB};
+use crate::order_assigner::order_protocol::{OrderEvent, OrderId, OrderIds, OrderMessage};
+use crate::order_assigner::cost_function;
 
-#[path = "./cost_function.rs"]
-mod cost_function;
-
+/// A hall order we have sent to another elevator, and not yet got an ack for
+/// * `to` the elevator we are waiting for
+/// * `next_candidates` who to try next if it does not answer, best fit first. Each is tried once.
+/// * `attempt` how many times the assign has been sent again to `to`
+/// * `deadline` when to send it again, or give up on `to`
+/// * `reassign` the order is ours, and we are moving it to a better car. We drop it on the ack, and keep it if `to` does not answer.
+struct Delivery {
+    button: CallButton,
+    to: usize,
+    next_candidates: Vec<usize>,
+    attempt: u32,
+    deadline: time::Instant,
+    reassign: bool,
+}
 
+#[allow(clippy::too_many_arguments)]
 pub fn hall_order_transmitter(
     connected_info_ch: cbc::Receiver<ConnectedElevatorInfo>,
     call_button_recv: cbc::Receiver<CallButton>,
+    replies: cbc::Receiver<OrderMessage>,
     set_pending: cbc::Sender<(bool, usize, CallButton)>,
-    assign_order_locally: cbc::Sender<CallButton>) {
+    new_hall_orders: cbc::Sender<CallButton>,
+    assign_order_locally: cbc::Sender<CallButton>,
+    handed_over: cbc::Sender<CallButton>,
+    events: cbc::Sender<OrderEvent>,
+    config: &Config) {
 
     let mut connected_elevator_info: ConnectedElevatorInfo;
-    let (check_if_active_tx, check_if_active_rx) = cbc::unbounded::<(usize, CallButton)>();
-    
-    let (send_bcast_tx, send_bcast_rx) = cbc::unbounded::<(usize, CallButton)>();
+    let mut order_ids = OrderIds::new(config.id());
+    let mut deliveries: HashMap<OrderId, Delivery> = HashMap::new();
+    let reassign_ticker = match config.reassign_period() {
+        Some(period) => cbc::tick(period),
+        None => cbc::never(),
+    };
+
+    let (send_bcast_tx, send_bcast_rx) = cbc::unbounded::<OrderMessage>();
 
     {
+    let port = config.order_port;
     spawn(move || {
-        crate::network_interface::bcast::tx(setting::ORDER_PORT, send_bcast_rx, 5);
+        crate::network_interface::bcast::tx(port, send_bcast_rx, 1);
     });
     }
 
+    let send_assign = |order_id: OrderId, delivery: &Delivery| {
+        send_bcast_tx.send(OrderMessage::Assign { order_id, to: delivery.to, button: delivery.button }).unwrap();
+        events.send(OrderEvent::AssignSent { order_id, to: delivery.to, button: delivery.button, attempt: delivery.attempt }).unwrap();
+    };
+    let assign_locally = |order_id: OrderId, button: CallButton| {
+        assign_order_locally.send(button).unwrap();
+        events.send(OrderEvent::AssignedLocally { order_id, button }).unwrap();
+    };
+    // Gives up on `delivery.to`. Returns the delivery to the next candidate, or None if there is nothing more to send.
+    let give_up = |order_id: OrderId, mut delivery: Delivery, connected_elevator_info: &ConnectedElevatorInfo| -> Option<Delivery> {
+        set_pending.send((false, delivery.to, delivery.button)).unwrap();
+        if delivery.reassign {
+            // It is still ours, so nothing is lost
+            events.send(OrderEvent::Kept { order_id, button: delivery.button }).unwrap();
+            return None;
+        }
+        let from = delivery.to;
+        if delivery.next_candidates.is_empty() {
+            // Everyone has been tried once. Going round again could keep picking a car that does not answer,
+            // so we take the order ourselves if we can.
+            let serves = connected_elevator_info.get_local_elevator_info()
+                .is_some_and(|local| local.serves(delivery.button.floor));
+            if serves {
+                events.send(OrderEvent::FallBack { order_id, from, to: config.id() }).unwrap();
+                assign_locally(order_id, delivery.button);
+            } else {
+                println!("No elevator took {:?}, giving up on it", delivery.button);
+                events.send(OrderEvent::Undelivered { order_id, button: delivery.button }).unwrap();
+            }
+            return None;
+        }
+        delivery.to = delivery.next_candidates.remove(0);
+        delivery.attempt = 0;
+        events.send(OrderEvent::FallBack { order_id, from, to: delivery.to }).unwrap();
+        if delivery.to == config.id() {
+            assign_locally(order_id, delivery.button);
+            return None;
+        }
+        set_pending.send((true, delivery.to, delivery.button)).unwrap();
+        return Some(delivery);
+    };
+
     cbc::select!{
         recv(connected_info_ch) -> a => {
             connected_elevator_info = a.unwrap();
         }
     }
     loop {
+        let next_deadline = match deliveries.values().map(|d| d.deadline).min() {
+            Some(deadline) => cbc::at(deadline),
+            None => cbc::never(),
+        };
         cbc::select!{
             recv(connected_info_ch) -> a => {
                 connected_elevator_info = a.unwrap();
@@ -40,51 +113,124 @@ pub fn hall_order_transmitter(
 
                 let call_button = a.unwrap();
                 println!("{:#?}", call_button.clone());
-                
+
                 if call_button.call == CAB {
                     assign_order_locally.send(call_button).unwrap();
+                    continue;
                 }
-                else {
-                    let lowest_cost_id = cost_function::find_lowest_cost_id(connected_elevator_info.clone(), call_button);
-                    if lowest_cost_id == setting::ID {
-                        assign_order_locally.send(call_button).unwrap();
-                    }
-                    else {
-                        send_bcast_tx.send((lowest_cost_id, call_button)).unwrap();
-                        set_pending.send((true, lowest_cost_id, call_button)).unwrap();
-                        let check_tx = check_if_active_tx.clone();
-                        spawn(move || {
-                            sleep(std::time::Duration::from_secs(1));
-                            check_tx.send((lowest_cost_id, call_button)).unwrap();
-                        });
+                if connected_elevator_info.get_newest_recall_signal().active {
+                    println!("Fire service recall is active, hall call refused");
+                    continue;
+                }
+                let mut candidates = cost_function::rank_candidates(&connected_elevator_info, call_button, config);
+                if candidates.is_empty() {
+                    println!("No elevator serves floor {}, hall call refused", call_button.floor);
+                    continue;
+                }
+                // The light goes on when every node has seen it, see `hall_orders.rs`
+                new_hall_orders.send(call_button).unwrap();
+                let order_id = order_ids.next_id();
+                let to = candidates.remove(0);
+                if to == config.id() {
+                    assign_locally(order_id, call_button);
+                    continue;
+                }
+                let delivery = Delivery {
+                    button: call_button,
+                    to,
+                    next_candidates: candidates,
+                    attempt: 0,
+                    deadline: time::Instant::now() + retry_delay(0, config),
+                    reassign: false,
+                };
+                send_assign(order_id, &delivery);
+                set_pending.send((true, to, call_button)).unwrap();
+                deliveries.insert(order_id, delivery);
+            },
+            recv(replies) -> a => {
+                match a.unwrap() {
+                    // A late ack from an elevator we gave up on also counts, it has taken the order
+                    OrderMessage::Ack { order_id, from } => {
+                        if let Some(delivery) = deliveries.remove(&order_id) {
+                            events.send(OrderEvent::AckReceived { order_id, from }).unwrap();
+                            if delivery.reassign {
+                                handed_over.send(delivery.button).unwrap();
+                                events.send(OrderEvent::HandedOver { order_id, to: from, button: delivery.button }).unwrap();
+                            }
+                        }
+                    },
+                    // Only the elevator we are waiting for can turn it down. The next one is tried right away.
+                    OrderMessage::Nack { order_id, from } => {
+                        if deliveries.get(&order_id).is_none_or(|delivery| delivery.to != from) {
+                            continue;
+                        }
+                        let delivery = deliveries.remove(&order_id).unwrap();
+                        events.send(OrderEvent::NackReceived { order_id, from }).unwrap();
+                        if let Some(mut delivery) = give_up(order_id, delivery, &connected_elevator_info) {
+                            delivery.deadline = time::Instant::now() + retry_delay(delivery.attempt, config);
+                            send_assign(order_id, &delivery);
+                            deliveries.insert(order_id, delivery);
+                        }
+                    },
+                    OrderMessage::Assign { .. } => {},
+                }
+            },
+            recv(reassign_ticker) -> _ => {
+                let local_info = match connected_elevator_info.get_local_elevator_info() {
+                    Some(info) => info,
+                    None => continue,
+                };
+                let hall_orders = connected_elevator_info.get_hall_orders();
+                for floor in 0..connected_elevator_info.get_num_floors() {
+                    for call in 0..CAB {
+                        let button = CallButton { floor, call };
+                        if !local_info.responsible_orders.is_active(button) || !hall_orders.is_confirmed(button)
+                            || deliveries.values().any(|d| d.button == button) {
+                            continue;
+                        }
+                        if let Some(to) = cost_function::reassignment_target(&connected_elevator_info, button, config) {
+                            let order_id = order_ids.next_id();
+                            let delivery = Delivery {
+                                button,
+                                to,
+                                next_candidates: Vec::new(),
+                                attempt: 0,
+                                deadline: time::Instant::now() + retry_delay(0, config),
+                                reassign: true,
+                            };
+                            send_assign(order_id, &delivery);
+                            set_pending.send((true, to, button)).unwrap();
+                            deliveries.insert(order_id, delivery);
+                        }
                     }
                 }
             },
-            recv(check_if_active_rx) -> a => {
-                let (id, button) = a.unwrap();
-                if !connected_elevator_info.is_active(id, button) {
-                    assign_order_locally.send(button).unwrap();
-                    set_pending.send((false, id, button)).unwrap();
+            recv(next_deadline) -> _ => {
+                let now = time::Instant::now();
+                let due: Vec<OrderId> = deliveries.iter()
+                    .filter(|(_, d)| d.deadline <= now)
+                    .map(|(&order_id, _)| order_id)
+                    .collect();
+                for order_id in due {
+                    let mut delivery = deliveries.remove(&order_id).unwrap();
+                    if delivery.attempt < config.assign_retries {
+                        delivery.attempt += 1;
+                    } else {
+                        delivery = match give_up(order_id, delivery, &connected_elevator_info) {
+                            Some(delivery) => delivery,
+                            None => continue,
+                        };
+                    }
+                    delivery.deadline = now + retry_delay(delivery.attempt, config);
+                    send_assign(order_id, &delivery);
+                    deliveries.insert(order_id, delivery);
                 }
             }
         }
     }
 }
 
-pub fn cab_order_backup_tx<ElevatorInfo: 'static + Clone + serde::Serialize + std::marker::Send>(
-    elev_info_rx: cbc::Receiver::<ElevatorInfo>){
-
-    let (send_bcast_tx, send_bcast_rx) = cbc::unbounded::<ElevatorInfo>();
-    spawn(move || {
-        crate::network_interface::bcast::tx(setting::CAB_BACKUP_PORT, send_bcast_rx, 10);
-    });
-    
-    loop {
-        cbc::select! {
-            recv(elev_info_rx) -> new_info => {
-                let elev_info = new_info.unwrap();
-                send_bcast_tx.send(elev_info.clone()).unwrap();
-            }
-        }
-    }
-}
\ No newline at end of file
+/// The delay before an assign is sent again, doubled for every attempt
+fn retry_delay(attempt: u32, config: &Config) -> time::Duration {
+    return config.assign_retry_delay() * 2u32.saturating_pow(attempt);
+}
@@ -0,0 +1,48 @@
+//! Decides where idle cars wait, so they are spread out over the building instead of bunched
+//! together wherever they finished their last order.
+use crate::global_elevator_info::connected_elevators::ConnectedElevatorInfo;
+use crate::local_elevator::fsm::elevatorfsm::{ElevatorInfo, State};
+use crate::util::config::Config;
+
+/// Finds the home floor of the local elevator among `config.parking_floors`.
+///
+/// The parking floors are given in order of importance, e.g. the lobby first. Each one in turn gets
+/// the nearest idle elevator serving it that has not been given one yet, ties going to the lowest ID, so every
+/// node comes to the same answer. A parking elevator counts as already being at its home floor.
+///
+/// `local_elev_info` - the newest info about the local elevator, which may not have reached `connected_elevator_info` yet
+///
+/// Returns `None` if there are more idle elevators than parking floors, and the local one is not needed.
+pub fn find_home_floor(
+    connected_elevator_info: &ConnectedElevatorInfo,
+    local_elev_info: &ElevatorInfo,
+    config: &Config) -> Option<u8> {
+
+    let local_id = connected_elevator_info.get_local_id();
+    let connected_elevators = connected_elevator_info.get_connected_elevators();
+    let mut idle_elevators: Vec<(usize, u8, &ElevatorInfo)> = connected_elevators.iter()
+        .filter(|elev| elev.id != local_id)
+        .chain(std::iter::once(local_elev_info))
+        .filter_map(|elev| match elev.state {
+            State::Idle if elev.floor != u8::MAX => Some((elev.id, elev.floor, elev)),
+            State::Parking { floor } => Some((elev.id, floor, elev)),
+            _ => None,
+        })
+        .collect();
+    idle_elevators.sort_unstable_by_key(|(id, _, _)| *id);
+
+    for &home_floor in config.parking_floors.iter() {
+        let nearest = idle_elevators.iter()
+            .enumerate()
+            .filter(|(_, (_, _, elev))| elev.serves(home_floor))
+            .min_by_key(|(_, (id, floor, _))| (floor.abs_diff(home_floor), *id))
+            .map(|(i, _)| i);
+        if let Some(i) = nearest {
+            let (id, _, _) = idle_elevators.remove(i);
+            if id == local_id {
+                return Some(home_floor);
+            }
+        }
+    }
+    return None;
+}
@@ -0,0 +1,181 @@
+//! Who takes over the hall orders of an elevator that is lost, can no longer move, or passed them because it is full.
+//!
+//! Every surviving node ranks the orphaned order the same way, with `cost_function::rank_for_takeover`,
+//! and only the first one in the list takes it. In case the nodes did not see quite the same costs, or the
+//! first one fails as well, the others keep watching the order: the one ranked `k` takes it after `k` times
+//! `takeover_timeout_millisec`, unless someone ahead of it has it active by then, or it has been served since it was
+//! orphaned. A serve from before that, e.g. a copy of the hall orders that has not caught up, does not count.
+use std::time;
+
+use crate::global_elevator_info::connected_elevators::ConnectedElevatorInfo;
+use crate::global_elevator_info::hall_orders::Lifecycle;
+use crate::local_elevator::elevio::poll::CallButton;
+use crate::order_assigner::cost_function;
+use crate::util::config::Config;
+
+/// An orphaned order we are not first in line for
+/// * `ahead` the elevators ranked before us
+/// * `deadline` when we take it, if none of them has
+/// * `seq` the sequence number of the hall order when it was orphaned
+struct Watch {
+    button: CallButton,
+    ahead: Vec<usize>,
+    deadline: time::Instant,
+    seq: u64,
+}
+
+pub struct Takeovers {
+    watching: Vec<Watch>,
+}
+
+impl Takeovers {
+    pub fn new() -> Takeovers {
+        Takeovers { watching: Vec::new() }
+    }
+
+    /// The `(failed elevator, order)` pairs were orphaned. Returns the orders we take right away.
+    pub fn orphaned(&mut self, info: &ConnectedElevatorInfo, orders: Vec<(usize, CallButton)>, config: &Config) -> Vec<CallButton> {
+        let now = time::Instant::now();
+        let mut take_now = Vec::new();
+        for (failed, button) in orders {
+            self.watching.retain(|watch| watch.button != button);
+            let ranking = cost_function::rank_for_takeover(info, button, &[failed], config);
+            match ranking.iter().position(|&id| id == info.get_local_id()) {
+                Some(0) => {
+                    println!("Taking over {:?} from elevator {}", button, failed);
+                    take_now.push(button);
+                },
+                Some(position) => self.watching.push(Watch {
+                    button,
+                    ahead: ranking[..position].to_vec(),
+                    deadline: now + config.takeover_timeout() * position as u32,
+                    seq: info.get_hall_orders().get(button).seq,
+                }),
+                None if ranking.is_empty() => println!("No elevator can take over {:?}", button),
+                None => {},
+            }
+        }
+        return take_now;
+    }
+
+    /// Forgets the orders that were taken or served, and returns those whose deadline passed without anyone
+    /// ahead of us taking them
+    pub fn check(&mut self, info: &ConnectedElevatorInfo) -> Vec<CallButton> {
+        let now = time::Instant::now();
+        let hall_orders = info.get_hall_orders();
+        self.watching.retain(|watch| {
+            let order = hall_orders.get(watch.button);
+            let served = order.state == Lifecycle::Served && order.seq > watch.seq;
+            return !served && !watch.ahead.iter().any(|&id| info.is_active(id, watch.button));
+        });
+
+        let (due, waiting): (Vec<Watch>, Vec<Watch>) = self.watching.drain(..).partition(|watch| watch.deadline <= now);
+        self.watching = waiting;
+        for watch in due.iter() {
+            println!("Nobody ahead of us took over {:?}, taking it", watch.button);
+        }
+        return due.into_iter().map(|watch| watch.button).collect();
+    }
+
+    pub fn next_deadline(&self) -> Option<time::Instant> {
+        return self.watching.iter().map(|watch| watch.deadline).min();
+    }
+}
+
+impl Default for Takeovers {
+    fn default() -> Takeovers {
+        return Takeovers::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::global_elevator_info::hall_orders::HallOrders;
+    use crate::local_elevator::fsm::elevatorfsm::{ElevatorInfo, State};
+
+    const FAILED: usize = 1;
+    const FIRST: usize = 2;
+    const LOCAL: usize = 3;
+    const DOWN_AT_3: CallButton = CallButton { floor: 3, call: 1 };
+
+    fn config() -> Config {
+        return Config { id: Some(LOCAL), takeover_timeout_millisec: 0, ..Default::default() };
+    }
+
+    /// The failed car and the one ranked first are both closer to the order than we are
+    fn building(config: &Config) -> ConnectedElevatorInfo {
+        let mut info = ConnectedElevatorInfo::new(ElevatorInfo::idle_at(LOCAL, 0), config);
+        info.update_remote_elevator_info(vec![ElevatorInfo::idle_at(FAILED, 3), ElevatorInfo::idle_at(FIRST, 2)]);
+        return info;
+    }
+
+    fn hall_orders_after(steps: &[fn(&mut HallOrders, CallButton)], config: &Config) -> HallOrders {
+        let mut hall_orders = HallOrders::new(config.num_floors);
+        for step in steps {
+            step(&mut hall_orders, DOWN_AT_3);
+        }
+        return hall_orders;
+    }
+
+    #[test]
+    fn only_the_first_ranked_takes_it_right_away() {
+        let config = config();
+        let info = building(&config);
+        let mut takeovers = Takeovers::new();
+        assert!(takeovers.orphaned(&info, vec![(FAILED, DOWN_AT_3)], &config).is_empty());
+        assert!(takeovers.next_deadline().is_some());
+
+        let first = Config { id: Some(FIRST), ..Default::default() };
+        let mut first_info = ConnectedElevatorInfo::new(ElevatorInfo::idle_at(FIRST, 2), &first);
+        first_info.update_remote_elevator_info(vec![ElevatorInfo::idle_at(FAILED, 3), ElevatorInfo::idle_at(LOCAL, 0)]);
+        assert_eq!(Takeovers::new().orphaned(&first_info, vec![(FAILED, DOWN_AT_3)], &first), vec![DOWN_AT_3]);
+    }
+
+    #[test]
+    fn taken_when_the_first_ranked_survivor_never_takes_it() {
+        let config = config();
+        let info = building(&config);
+        let mut takeovers = Takeovers::new();
+        takeovers.orphaned(&info, vec![(FAILED, DOWN_AT_3)], &config);
+        assert_eq!(takeovers.check(&info), vec![DOWN_AT_3]);
+        assert!(takeovers.next_deadline().is_none());
+    }
+
+    #[test]
+    fn dropped_when_someone_ahead_has_it_active() {
+        let config = config();
+        let mut info = building(&config);
+        let mut takeovers = Takeovers::new();
+        takeovers.orphaned(&info, vec![(FAILED, DOWN_AT_3)], &config);
+
+        let mut first = ElevatorInfo::idle_at(FIRST, 2);
+        first.state = State::Moving;
+        first.responsible_orders.set_active(DOWN_AT_3);
+        info.update_remote_elevator_info(vec![ElevatorInfo::idle_at(FAILED, 3), first]);
+        assert!(takeovers.check(&info).is_empty());
+        assert!(takeovers.next_deadline().is_none());
+    }
+
+    #[test]
+    fn a_serve_from_before_the_failure_does_not_count() {
+        let config = config();
+        let mut info = building(&config);
+        info.merge_hall_orders(&hall_orders_after(&[HallOrders::press, HallOrders::serve], &config));
+        let mut takeovers = Takeovers::new();
+        takeovers.orphaned(&info, vec![(FAILED, DOWN_AT_3)], &config);
+        assert_eq!(takeovers.check(&info), vec![DOWN_AT_3]);
+    }
+
+    #[test]
+    fn dropped_when_served_after_the_failure() {
+        let config = config();
+        let mut info = building(&config);
+        info.merge_hall_orders(&hall_orders_after(&[HallOrders::press], &config));
+        let mut takeovers = Takeovers::new();
+        takeovers.orphaned(&info, vec![(FAILED, DOWN_AT_3)], &config);
+
+        info.merge_hall_orders(&hall_orders_after(&[HallOrders::press, HallOrders::serve], &config));
+        assert!(takeovers.check(&info).is_empty());
+    }
+}
@@ -0,0 +1,254 @@
+//! Physics of a single simulated elevator car, and the 4-byte protocol used to talk to it.
+use std::fmt;
+use std::time;
+
+use crate::local_elevator::elevio::elev::{DIRN_DOWN, DIRN_STOP, DIRN_UP};
+
+pub const OP_MOTOR_DIRECTION: u8 = 1;
+pub const OP_CALL_BUTTON_LIGHT: u8 = 2;
+pub const OP_FLOOR_INDICATOR: u8 = 3;
+pub const OP_DOOR_LIGHT: u8 = 4;
+pub const OP_STOP_BUTTON_LIGHT: u8 = 5;
+pub const OP_CALL_BUTTON: u8 = 6;
+pub const OP_FLOOR_SENSOR: u8 = 7;
+pub const OP_STOP_BUTTON: u8 = 8;
+pub const OP_OBSTRUCTION: u8 = 9;
+/// Not part of the course protocol. Replies with the load of the car in percent.
+pub const OP_LOAD: u8 = 10;
+
+/// How the simulated building and car behave.
+/// * `num_floors` - number of floors served by the car
+/// * `travel_time` - time it takes to travel from one floor to the next
+/// * `sensor_width` - fraction of the distance between two floors where the floor sensor is active
+/// * `door_interlock` - if set, the motor refuses to move while the door light is on
+/// * `start_floor` - initial position of the car, may be between two floors
+/// * `press_duration` - how long a scripted button press is held down
+#[derive(Clone, Debug)]
+pub struct SimSettings {
+    pub num_floors: u8,
+    pub travel_time: time::Duration,
+    pub sensor_width: f64,
+    pub door_interlock: bool,
+    pub start_floor: f64,
+    pub press_duration: time::Duration,
+}
+
+impl Default for SimSettings {
+    fn default() -> SimSettings {
+        SimSettings {
+            num_floors: 4,
+            travel_time: time::Duration::from_millis(2000),
+            sensor_width: 0.1,
+            door_interlock: false,
+            start_floor: 0.5,
+            press_duration: time::Duration::from_millis(200),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// The motor ignores all commands and the car stays where it is
+    MotorPowerLoss,
+    /// The obstruction switch reads active no matter what
+    StuckObstruction,
+    /// The floor sensor never reports the given floor, or any floor if `None`
+    SensorDropout { floor: Option<u8> },
+}
+
+#[derive(Clone, Debug)]
+pub struct ElevatorSim {
+    settings: SimSettings,
+    position: f64,
+    motor_dirn: u8,
+    door_light: bool,
+    stop_light: bool,
+    floor_indicator: u8,
+    button_lights: Vec<[bool; 3]>,
+    button_release: Vec<[Option<time::Instant>; 3]>,
+    obstruction: bool,
+    stop_button: bool,
+    load: u8,
+    motor_power_loss: bool,
+    stuck_obstruction: bool,
+    sensor_dropout: Vec<bool>,
+}
+
+impl ElevatorSim {
+    pub fn new(settings: SimSettings) -> ElevatorSim {
+        let n = usize::from(settings.num_floors);
+        let top = f64::from(settings.num_floors - 1);
+        ElevatorSim {
+            position: settings.start_floor.max(0.0).min(top),
+            settings,
+            motor_dirn: DIRN_STOP,
+            door_light: false,
+            stop_light: false,
+            floor_indicator: 0,
+            button_lights: vec![[false; 3]; n],
+            button_release: vec![[None; 3]; n],
+            obstruction: false,
+            stop_button: false,
+            load: 0,
+            motor_power_loss: false,
+            stuck_obstruction: false,
+            sensor_dropout: vec![false; n],
+        }
+    }
+
+    /// Moves the car according to the motor direction.
+    /// Returns a description of anything noteworthy that happened during the step.
+    pub fn step(&mut self, dt: time::Duration) -> Option<String> {
+        if self.motor_power_loss || self.motor_dirn == DIRN_STOP {
+            return None;
+        }
+        if self.settings.door_interlock && self.door_light {
+            return None;
+        }
+        let prev_sensor = self.floor_sensor();
+        let prev_position = self.position;
+        let distance = dt.as_secs_f64() / self.settings.travel_time.as_secs_f64();
+        let top = f64::from(self.settings.num_floors - 1);
+        let mut event = None;
+        match self.motor_dirn {
+            DIRN_UP => self.position += distance,
+            DIRN_DOWN => self.position -= distance,
+            _ => {}
+        }
+        if self.position > top || self.position < 0.0 {
+            self.position = self.position.max(0.0).min(top);
+            if self.position != prev_position {
+                event = Some(format!("Car hit the end of the shaft at floor {}", self.position));
+            }
+        }
+        if self.door_light {
+            event = Some("Car is moving with the door open!".to_string());
+        }
+        match self.floor_sensor() {
+            Some(f) if prev_sensor != Some(f) => Some(format!("Arrived at floor {}", f)),
+            _ => event,
+        }
+    }
+
+    /// Handles one request in the 4-byte protocol, returning the reply if the request expects one.
+    pub fn handle(&mut self, req: [u8; 4]) -> Option<[u8; 4]> {
+        let now = time::Instant::now();
+        match req[0] {
+            OP_MOTOR_DIRECTION => {
+                self.motor_dirn = req[1];
+                None
+            }
+            OP_CALL_BUTTON_LIGHT => {
+                if let Some(lights) = self.button_lights.get_mut(usize::from(req[2])) {
+                    if req[1] < 3 {
+                        lights[usize::from(req[1])] = req[3] != 0;
+                    }
+                }
+                None
+            }
+            OP_FLOOR_INDICATOR => {
+                self.floor_indicator = req[1];
+                None
+            }
+            OP_DOOR_LIGHT => {
+                self.door_light = req[1] != 0;
+                None
+            }
+            OP_STOP_BUTTON_LIGHT => {
+                self.stop_light = req[1] != 0;
+                None
+            }
+            OP_CALL_BUTTON => {
+                let pressed = match self.button_release.get(usize::from(req[2])) {
+                    Some(release) if req[1] < 3 => release[usize::from(req[1])].is_some_and(|t| now < t),
+                    _ => false,
+                };
+                Some([OP_CALL_BUTTON, pressed as u8, 0, 0])
+            }
+            OP_FLOOR_SENSOR => match self.floor_sensor() {
+                Some(f) => Some([OP_FLOOR_SENSOR, 1, f, 0]),
+                None => Some([OP_FLOOR_SENSOR, 0, 0, 0]),
+            },
+            OP_STOP_BUTTON => Some([OP_STOP_BUTTON, self.stop_button as u8, 0, 0]),
+            OP_OBSTRUCTION => Some([OP_OBSTRUCTION, (self.obstruction || self.stuck_obstruction) as u8, 0, 0]),
+            OP_LOAD => Some([OP_LOAD, self.load, 0, 0]),
+            _ => None,
+        }
+    }
+
+    pub fn floor_sensor(&self) -> Option<u8> {
+        let nearest = self.position.round();
+        if (self.position - nearest).abs() > self.settings.sensor_width / 2.0 {
+            return None;
+        }
+        let floor = nearest as u8;
+        if self.sensor_dropout[usize::from(floor)] {
+            return None;
+        }
+        return Some(floor);
+    }
+
+    /// Holds the button down for `press_duration`.
+    pub fn press(&mut self, floor: u8, call: u8) -> Result<(), String> {
+        if floor >= self.settings.num_floors || call > 2 {
+            return Err(format!("no button {} on floor {}", call, floor));
+        }
+        let release = time::Instant::now() + self.settings.press_duration;
+        self.button_release[usize::from(floor)][usize::from(call)] = Some(release);
+        return Ok(());
+    }
+
+    pub fn set_obstruction(&mut self, active: bool) {
+        self.obstruction = active;
+    }
+
+    pub fn set_stop_button(&mut self, pressed: bool) {
+        self.stop_button = pressed;
+    }
+
+    pub fn set_load(&mut self, percent: u8) -> Result<(), String> {
+        if percent > 100 {
+            return Err(format!("load must be at most 100%, was {}", percent));
+        }
+        self.load = percent;
+        return Ok(());
+    }
+
+    pub fn set_fault(&mut self, fault: Fault, active: bool) -> Result<(), String> {
+        match fault {
+            Fault::MotorPowerLoss => self.motor_power_loss = active,
+            Fault::StuckObstruction => self.stuck_obstruction = active,
+            Fault::SensorDropout { floor: Some(f) } => match self.sensor_dropout.get_mut(usize::from(f)) {
+                Some(dropout) => *dropout = active,
+                None => return Err(format!("no floor {}", f)),
+            },
+            Fault::SensorDropout { floor: None } => {
+                for dropout in self.sensor_dropout.iter_mut() {
+                    *dropout = active;
+                }
+            }
+        }
+        return Ok(());
+    }
+}
+
+impl fmt::Display for ElevatorSim {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dirn = match self.motor_dirn {
+            DIRN_UP => "up",
+            DIRN_DOWN => "down",
+            _ => "stop",
+        };
+        writeln!(f, "position: {:.2}  motor: {}  door: {}  stop light: {}  floor indicator: {}",
+            self.position, dirn, self.door_light, self.stop_light, self.floor_indicator)?;
+        writeln!(f, "obstruction: {}  stop button: {}  load: {}%", self.obstruction, self.stop_button, self.load)?;
+        writeln!(f, "faults: motor power loss: {}  stuck obstruction: {}  sensor dropout: {:?}",
+            self.motor_power_loss, self.stuck_obstruction, self.sensor_dropout)?;
+        for floor in (0..self.button_lights.len()).rev() {
+            let lights = self.button_lights[floor];
+            writeln!(f, "  floor {}: up {}  down {}  cab {}", floor,
+                lights[0] as u8, lights[1] as u8, lights[2] as u8)?;
+        }
+        return Ok(());
+    }
+}
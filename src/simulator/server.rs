@@ -0,0 +1,244 @@
+//! TCP front end of the simulator, speaking the same 4-byte protocol as the course hardware server.
+//!
+//! Buttons, switches and faults are controlled by commands, read from stdin and
+//! optionally from a script file given with `--script PATH`:
+//!
+//! ```text
+//! call <floor> <up|down|cab>          press a call button
+//! obstruction <on|off>                flip the obstruction switch
+//! stop <on|off>                       hold or release the stop button
+//! load <percent>                      set the load of the car
+//! fault motor-loss <on|off>           cut power to the motor
+//! fault stuck-obstruction <on|off>    make the obstruction switch read active
+//! fault sensor-dropout <floor|all> <on|off>
+//! wait <millisec>                     pause the script
+//! status                              print the state of the car
+//! ```
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::*;
+use std::thread::{sleep, spawn};
+use std::time;
+
+use super::physics::{ElevatorSim, Fault, SimSettings};
+use crate::local_elevator::elevio::poll::{CAB, HALL_DOWN, HALL_UP};
+use crate::util::config::{parse_args, ConfigError};
+use crate::util::constants as setting;
+
+const PHYSICS_PERIOD_MILLISEC: u64 = 10;
+
+#[derive(Clone, Debug)]
+pub struct SimConfig {
+    pub port: u16,
+    pub script: Option<String>,
+    pub settings: SimSettings,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SimCommand {
+    Call { floor: u8, call: u8 },
+    Obstruction { active: bool },
+    Stop { pressed: bool },
+    Load { percent: u8 },
+    Fault { fault: Fault, active: bool },
+    Wait { duration: time::Duration },
+    Status,
+}
+
+impl SimConfig {
+    /// Parses `--port`, `--num-floors`, `--travel-time-millisec`, `--sensor-width`,
+    /// `--door-interlock`, `--start-floor` and `--script`.
+    pub fn from_args(args: &[String]) -> Result<SimConfig, ConfigError> {
+        let (positional, flags) = parse_args(args)?;
+        let mut config = SimConfig {
+            port: setting::HW_SERVER_PORT,
+            script: None,
+            settings: SimSettings::default(),
+        };
+        if let Some(port) = positional {
+            config.port = parse_value("port", &port)?;
+        }
+        for (flag, value) in flags.iter() {
+            match flag.as_str() {
+                "port" => config.port = parse_value(flag, value)?,
+                "num-floors" => config.settings.num_floors = parse_value(flag, value)?,
+                "travel-time-millisec" => {
+                    config.settings.travel_time = time::Duration::from_millis(parse_value(flag, value)?)
+                }
+                "sensor-width" => config.settings.sensor_width = parse_value(flag, value)?,
+                "door-interlock" => config.settings.door_interlock = parse_value(flag, value)?,
+                "start-floor" => config.settings.start_floor = parse_value(flag, value)?,
+                "script" => config.script = Some(value.clone()),
+                _ => return Err(ConfigError::UnknownSetting(format!("--{}", flag))),
+            }
+        }
+        if config.settings.num_floors < 2 {
+            return Err(ConfigError::Invalid("num-floors must be at least 2".to_string()));
+        }
+        if config.settings.travel_time.as_millis() == 0 {
+            return Err(ConfigError::Invalid("travel-time-millisec must be non-zero".to_string()));
+        }
+        if !(0.0..1.0).contains(&config.settings.sensor_width) {
+            return Err(ConfigError::Invalid("sensor-width must be in [0, 1)".to_string()));
+        }
+        return Ok(config);
+    }
+}
+
+impl std::str::FromStr for SimCommand {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<SimCommand, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["call", floor, call] => {
+                let floor = floor.parse().map_err(|_| format!("invalid floor '{}'", floor))?;
+                let call = match *call {
+                    "up" => HALL_UP,
+                    "down" => HALL_DOWN,
+                    "cab" => CAB,
+                    _ => return Err(format!("invalid call '{}', expected up, down or cab", call)),
+                };
+                Ok(SimCommand::Call { floor, call })
+            }
+            ["obstruction", on] => Ok(SimCommand::Obstruction { active: parse_on_off(on)? }),
+            ["stop", on] => Ok(SimCommand::Stop { pressed: parse_on_off(on)? }),
+            ["load", percent] => {
+                let percent = percent.parse().map_err(|_| format!("invalid load '{}'", percent))?;
+                Ok(SimCommand::Load { percent })
+            }
+            ["fault", "motor-loss", on] => Ok(SimCommand::Fault { fault: Fault::MotorPowerLoss, active: parse_on_off(on)? }),
+            ["fault", "stuck-obstruction", on] => {
+                Ok(SimCommand::Fault { fault: Fault::StuckObstruction, active: parse_on_off(on)? })
+            }
+            ["fault", "sensor-dropout", floor, on] => {
+                let floor = match *floor {
+                    "all" => None,
+                    f => Some(f.parse().map_err(|_| format!("invalid floor '{}'", f))?),
+                };
+                Ok(SimCommand::Fault { fault: Fault::SensorDropout { floor }, active: parse_on_off(on)? })
+            }
+            ["wait", ms] => {
+                let ms = ms.parse().map_err(|_| format!("invalid duration '{}'", ms))?;
+                Ok(SimCommand::Wait { duration: time::Duration::from_millis(ms) })
+            }
+            ["status"] => Ok(SimCommand::Status),
+            _ => Err(format!("unknown command '{}'", line)),
+        }
+    }
+}
+
+/// Runs the simulator until the process is killed.
+pub fn run(config: SimConfig) -> std::io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", config.port))?;
+    println!("Simulator listening on port {} with {} floors", config.port, config.settings.num_floors);
+
+    let sim = Arc::new(Mutex::new(ElevatorSim::new(config.settings.clone())));
+
+    {
+        let sim = sim.clone();
+        spawn(move || {
+            let period = time::Duration::from_millis(PHYSICS_PERIOD_MILLISEC);
+            let mut last = time::Instant::now();
+            loop {
+                sleep(period);
+                let now = time::Instant::now();
+                if let Some(event) = sim.lock().unwrap().step(now - last) {
+                    println!("{}", event);
+                }
+                last = now;
+            }
+        });
+    }
+
+    if let Some(path) = config.script.clone() {
+        let sim = sim.clone();
+        let file = std::fs::File::open(&path)?;
+        spawn(move || run_commands(BufReader::new(file), &sim));
+    }
+
+    {
+        let sim = sim.clone();
+        spawn(move || run_commands(BufReader::new(std::io::stdin()), &sim));
+    }
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let sim = sim.clone();
+                spawn(move || serve_client(stream, &sim));
+            }
+            Err(e) => println!("Failed to accept connection: {}", e),
+        }
+    }
+    return Ok(());
+}
+
+fn serve_client(mut stream: TcpStream, sim: &Mutex<ElevatorSim>) {
+    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    println!("Client connected: {}", peer);
+    let mut req = [0u8; 4];
+    loop {
+        if stream.read_exact(&mut req).is_err() {
+            break;
+        }
+        let reply = sim.lock().unwrap().handle(req);
+        if let Some(reply) = reply {
+            if stream.write_all(&reply).is_err() {
+                break;
+            }
+        }
+    }
+    println!("Client disconnected: {}", peer);
+}
+
+fn run_commands<R: BufRead>(reader: R, sim: &Mutex<ElevatorSim>) {
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        match line.parse::<SimCommand>() {
+            Ok(SimCommand::Wait { duration }) => sleep(duration),
+            Ok(cmd) => {
+                if let Err(e) = apply(cmd, &mut sim.lock().unwrap()) {
+                    println!("{}", e);
+                }
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+fn apply(cmd: SimCommand, sim: &mut ElevatorSim) -> Result<(), String> {
+    match cmd {
+        SimCommand::Call { floor, call } => sim.press(floor, call)?,
+        SimCommand::Obstruction { active } => sim.set_obstruction(active),
+        SimCommand::Stop { pressed } => sim.set_stop_button(pressed),
+        SimCommand::Load { percent } => sim.set_load(percent)?,
+        SimCommand::Fault { fault, active } => {
+            sim.set_fault(fault, active)?;
+            println!("Fault {:?} {}", fault, if active { "on" } else { "off" });
+        }
+        SimCommand::Status => print!("{}", sim),
+        SimCommand::Wait { .. } => {}
+    }
+    return Ok(());
+}
+
+fn parse_on_off(word: &str) -> Result<bool, String> {
+    match word {
+        "on" | "1" | "true" => Ok(true),
+        "off" | "0" | "false" => Ok(false),
+        _ => Err(format!("expected on or off, got '{}'", word)),
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, ConfigError> {
+    return value.parse()
+        .map_err(|_| ConfigError::Invalid(format!("invalid value '{}' for {}", value, name)));
+}
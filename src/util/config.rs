@@ -0,0 +1,521 @@
+//! Runtime configuration of a single elevator node.
+//!
+//! Settings are resolved in the following order, where later sources override earlier ones:
+//! 1. The defaults given in `util/constants.rs`
+//! 2. A TOML or JSON file given by `--config PATH` or `ELEVATOR_CONFIG=PATH`
+//! 3. Environment variables on the form `ELEVATOR_<SETTING>`, e.g. `ELEVATOR_ID=2`
+//! 4. Command line flags on the form `--<setting> VALUE`, e.g. `--num-floors 6`
+//!
+//! For backwards compatibility, a single positional argument is interpreted as the simulator port,
+//! so `cargo run 15658` still works.
+use serde;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::time;
+
+use crate::local_elevator::fsm::elevatorfsm::ClearPolicy;
+use crate::order_assigner::cost_function::StrategyKind;
+use crate::util::constants as setting;
+
+const ENV_PREFIX: &str = "ELEVATOR_";
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Claimed at startup if not set, see `global_elevator_info/node_id.rs`. Use `id()` once it is claimed.
+    pub id: Option<usize>,
+    pub hw_server: String,
+
+    pub order_port: u16,
+    pub cab_backup_port: u16,
+    pub elev_info_port: u16,
+
+    pub time_until_peer_lost_millisec: u64,
+    pub info_transmit_period_millisec: u64,
+    /// How sure we must be that a silent peer is gone before it is suspected, see `failure_detector.rs`
+    pub phi_suspect: f64,
+    /// ... and before it is lost and its hall orders are taken over. It is never lost before `time_until_peer_lost_millisec`.
+    pub phi_lost: f64,
+    /// The least jitter assumed when packets arrive very regularly
+    pub heartbeat_min_std_dev_millisec: u64,
+    pub cab_backup_period_millisec: u64,
+    /// How many times a hall order is sent again to an elevator that does not answer, before the next one is tried
+    pub assign_retries: u32,
+    /// The delay before the first retry, doubled for every retry after it
+    pub assign_retry_millisec: u64,
+    /// How long each survivor waits for the ones ranked before it to take over an orphaned hall order, see `takeover.rs`
+    pub takeover_timeout_millisec: u64,
+
+    pub motor_timeout_duration_sec: u64,
+    pub obstructed_time_before_reassign_sec: u64,
+
+    pub num_floors: u8,
+    pub door_open_time: u64,
+    /// `all` or `in_direction`, see `ClearPolicy`
+    pub order_clearing: ClearPolicy,
+    /// How hall orders are given to the elevators, see `StrategyKind`
+    pub dispatch_strategy: StrategyKind,
+    /// The `hall_request_assigner` executable used by that strategy. It is done in-process if empty.
+    pub hall_request_assigner_path: String,
+    /// How long the executable gets to answer before it is killed and the cars are ranked by time to idle
+    pub hall_request_assigner_timeout_millisec: u64,
+    /// How often each node looks for better cars for the hall orders it has. Off if 0.
+    pub reassign_period_sec: u64,
+    /// How much cheaper another car must be before an order is moved to it, in the units of the cost function
+    pub reassign_hysteresis: u64,
+    /// Floors where idle cars wait, most important first. Parking is off if empty.
+    pub parking_floors: Vec<u8>,
+    pub parking_idle_time_sec: u64,
+    /// Where all cars go during fire service recall
+    pub recall_floor: u8,
+    /// The floors this car can reach. All floors are served if empty.
+    pub served_floors: Vec<u8>,
+    pub locked_floors: Vec<u8>,
+    /// Where the orders and floor of this node are saved, so they survive a restart. Nothing is saved if empty.
+    pub state_dir: String,
+
+    pub poll_call_buttons_millisec: u64,
+    pub poll_floor_sensor_millisec: u64,
+    pub poll_stop_button_millisec: u64,
+    pub poll_obstruction_millisec: u64,
+    pub debounce_call_buttons_millisec: u64,
+    pub debounce_floor_sensor_millisec: u64,
+    pub debounce_stop_button_millisec: u64,
+    pub debounce_obstruction_millisec: u64,
+
+    /// Set if the hardware server reports the load of the car
+    pub load_sensor: bool,
+    pub poll_load_millisec: u64,
+    pub debounce_load_millisec: u64,
+    /// From this load, in percent, the car is full and passes hall calls
+    pub full_load_percent: u8,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io { path: String, err: io::Error },
+    Parse { source: String, msg: String },
+    UnknownSetting(String),
+    MissingValue(String),
+    Invalid(String),
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            id: None,
+            hw_server: format!("localhost:{}", setting::HW_SERVER_PORT),
+            order_port: setting::ORDER_PORT,
+            cab_backup_port: setting::CAB_BACKUP_PORT,
+            elev_info_port: setting::ELEV_INFO_PORT,
+            time_until_peer_lost_millisec: setting::TIME_UNTIL_PEER_LOST_MILLISEC,
+            info_transmit_period_millisec: setting::INFO_TRANSMIT_PERIOD_MILLISEC,
+            phi_suspect: setting::PHI_SUSPECT,
+            phi_lost: setting::PHI_LOST,
+            heartbeat_min_std_dev_millisec: setting::HEARTBEAT_MIN_STD_DEV_MILLISEC,
+            cab_backup_period_millisec: setting::CAB_BACKUP_PERIOD_MILLISEC,
+            assign_retries: setting::ASSIGN_RETRIES,
+            assign_retry_millisec: setting::ASSIGN_RETRY_MILLISEC,
+            takeover_timeout_millisec: setting::TAKEOVER_TIMEOUT_MILLISEC,
+            motor_timeout_duration_sec: setting::MOTOR_TIMEOUT_DURATION_SEC,
+            obstructed_time_before_reassign_sec: setting::OBSTRUCTED_TIME_BEFORE_REASSIGN_SEC,
+            num_floors: setting::ELEV_NUM_FLOORS,
+            door_open_time: setting::DOOR_OPEN_TIME,
+            order_clearing: ClearPolicy::All,
+            dispatch_strategy: StrategyKind::TimeToIdle,
+            hall_request_assigner_path: String::new(),
+            hall_request_assigner_timeout_millisec: setting::HALL_REQUEST_ASSIGNER_TIMEOUT_MILLISEC,
+            reassign_period_sec: 0,
+            reassign_hysteresis: setting::REASSIGN_HYSTERESIS,
+            parking_floors: Vec::new(),
+            parking_idle_time_sec: setting::PARKING_IDLE_TIME_SEC,
+            recall_floor: setting::RECALL_FLOOR,
+            served_floors: Vec::new(),
+            locked_floors: Vec::new(),
+            state_dir: setting::STATE_DIR.to_string(),
+            poll_call_buttons_millisec: setting::POLL_PERIOD_MILLISEC,
+            poll_floor_sensor_millisec: setting::POLL_PERIOD_MILLISEC,
+            poll_stop_button_millisec: setting::POLL_PERIOD_MILLISEC,
+            poll_obstruction_millisec: setting::POLL_PERIOD_MILLISEC,
+            debounce_call_buttons_millisec: 0,
+            debounce_floor_sensor_millisec: 0,
+            debounce_stop_button_millisec: 0,
+            debounce_obstruction_millisec: 0,
+            load_sensor: false,
+            poll_load_millisec: setting::POLL_PERIOD_MILLISEC,
+            debounce_load_millisec: setting::DEBOUNCE_LOAD_MILLISEC,
+            full_load_percent: setting::FULL_LOAD_PERCENT,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration from the process arguments and environment.
+    pub fn from_env() -> Result<Config, ConfigError> {
+        let args: Vec<String> = env::args().skip(1).collect();
+        return Config::load(&args, env::vars());
+    }
+
+    /// Resolves the configuration from the given arguments (without the program name) and environment variables.
+    ///
+    /// * `args` - command line arguments, e.g. `["--id", "2", "--config", "elevator.toml"]`
+    /// * `vars` - environment variables, e.g. from `std::env::vars()`
+    pub fn load<I: IntoIterator<Item = (String, String)>>(args: &[String], vars: I) -> Result<Config, ConfigError> {
+        let (positional, flags) = parse_args(args)?;
+        let vars: Vec<(String, String)> = vars.into_iter().collect();
+
+        let mut settings = match serde_json::to_value(Config::default()) {
+            Ok(serde_json::Value::Object(map)) => map,
+            _ => unreachable!(),
+        };
+
+        let config_file = flags.iter().rev().find(|(k, _)| k == "config").map(|(_, v)| v.clone())
+            .or_else(|| vars.iter().find(|(k, _)| k == "ELEVATOR_CONFIG").map(|(_, v)| v.clone()));
+        if let Some(path) = config_file {
+            for (key, value) in read_file(&path)? {
+                if !settings.contains_key(&key) {
+                    return Err(ConfigError::UnknownSetting(format!("{} (in {})", key, path)));
+                }
+                settings.insert(key, value);
+            }
+        }
+
+        for (var, raw) in vars.iter() {
+            if let Some(key) = var.strip_prefix(ENV_PREFIX) {
+                let key = key.to_lowercase();
+                if key != "config" && settings.contains_key(&key) {
+                    let value = parse_override(&settings[&key], raw, var)?;
+                    settings.insert(key, value);
+                }
+            }
+        }
+
+        for (flag, raw) in flags.iter() {
+            if flag == "config" {
+                continue;
+            }
+            let key = flag.replace('-', "_");
+            if !settings.contains_key(&key) {
+                return Err(ConfigError::UnknownSetting(format!("--{}", flag)));
+            }
+            let value = parse_override(&settings[&key], raw, &format!("--{}", flag))?;
+            settings.insert(key, value);
+        }
+
+        if let Some(port) = positional {
+            let port: u16 = port.parse()
+                .map_err(|_| ConfigError::Invalid(format!("simulator port '{}' is not a valid port", port)))?;
+            settings.insert("hw_server".to_string(), serde_json::Value::String(format!("localhost:{}", port)));
+        }
+
+        let config: Config = serde_json::from_value(serde_json::Value::Object(settings))
+            .map_err(|e| ConfigError::Parse { source: "settings".to_string(), msg: e.to_string() })?;
+        config.validate()?;
+        return Ok(config);
+    }
+
+    /// Checks that the settings describe an elevator we are able to run.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.num_floors < 2 {
+            return Err(ConfigError::Invalid(format!("num_floors must be at least 2, was {}", self.num_floors)));
+        }
+        if self.door_open_time == 0 {
+            return Err(ConfigError::Invalid("door_open_time must be at least 1 second".to_string()));
+        }
+        if self.order_port == self.cab_backup_port || self.order_port == self.elev_info_port
+            || self.cab_backup_port == self.elev_info_port {
+            return Err(ConfigError::Invalid("order_port, cab_backup_port and elev_info_port must be different".to_string()));
+        }
+        if self.info_transmit_period_millisec == 0
+            || self.info_transmit_period_millisec >= self.time_until_peer_lost_millisec {
+            return Err(ConfigError::Invalid(
+                "info_transmit_period_millisec must be non-zero and shorter than time_until_peer_lost_millisec".to_string()));
+        }
+        if !(self.phi_suspect > 0.0 && self.phi_suspect < self.phi_lost) || self.heartbeat_min_std_dev_millisec == 0 {
+            return Err(ConfigError::Invalid(
+                "phi_suspect must be positive and below phi_lost, and heartbeat_min_std_dev_millisec non-zero".to_string()));
+        }
+        if self.cab_backup_period_millisec == 0 || self.cab_backup_period_millisec >= self.time_until_peer_lost_millisec {
+            return Err(ConfigError::Invalid(
+                "cab_backup_period_millisec must be non-zero and shorter than time_until_peer_lost_millisec".to_string()));
+        }
+        if self.assign_retry_millisec == 0 || self.assign_retries > 10 {
+            return Err(ConfigError::Invalid("assign_retry_millisec must be non-zero, and assign_retries at most 10".to_string()));
+        }
+        if self.takeover_timeout_millisec == 0 {
+            return Err(ConfigError::Invalid("takeover_timeout_millisec must be non-zero".to_string()));
+        }
+        if self.hall_request_assigner_timeout_millisec == 0 {
+            return Err(ConfigError::Invalid("hall_request_assigner_timeout_millisec must be non-zero".to_string()));
+        }
+        if self.poll_call_buttons_millisec == 0 || self.poll_floor_sensor_millisec == 0
+            || self.poll_stop_button_millisec == 0 || self.poll_obstruction_millisec == 0
+            || (self.load_sensor && self.poll_load_millisec == 0) {
+            return Err(ConfigError::Invalid("poll periods must be non-zero".to_string()));
+        }
+        if let Some(floor) = self.parking_floors.iter().find(|&&f| f >= self.num_floors) {
+            return Err(ConfigError::Invalid(format!("parking floor {} is not below num_floors", floor)));
+        }
+        if let Some(floor) = self.served_floors.iter().chain(self.locked_floors.iter()).find(|&&f| f >= self.num_floors) {
+            return Err(ConfigError::Invalid(format!("served or locked floor {} is not below num_floors", floor)));
+        }
+        if self.full_load_percent == 0 || self.full_load_percent > 100 {
+            return Err(ConfigError::Invalid(format!("full_load_percent must be in 1..=100, was {}", self.full_load_percent)));
+        }
+        if self.recall_floor >= self.num_floors {
+            return Err(ConfigError::Invalid(format!("recall_floor must be below num_floors, was {}", self.recall_floor)));
+        }
+        if !self.get_served_floors().contains(&self.recall_floor) || self.locked_floors.contains(&self.recall_floor) {
+            return Err(ConfigError::Invalid(format!("recall_floor {} must be served by the car and not locked", self.recall_floor)));
+        }
+        if self.hw_server.is_empty() {
+            return Err(ConfigError::Invalid("hw_server can not be empty".to_string()));
+        }
+        return Ok(());
+    }
+
+    /// The id of this node. It is claimed in `main` before anything else starts.
+    pub fn id(&self) -> usize {
+        return self.id.expect("the node id is claimed before it is used");
+    }
+
+    pub fn door_open_duration(&self) -> time::Duration {
+        return time::Duration::from_secs(self.door_open_time);
+    }
+
+    pub fn motor_timeout(&self) -> time::Duration {
+        return time::Duration::from_secs(self.motor_timeout_duration_sec);
+    }
+
+    pub fn obstruction_timeout(&self) -> time::Duration {
+        return time::Duration::from_secs(self.obstructed_time_before_reassign_sec);
+    }
+
+    pub fn peer_lost_timeout(&self) -> time::Duration {
+        return time::Duration::from_millis(self.time_until_peer_lost_millisec);
+    }
+
+    pub fn info_transmit_period(&self) -> time::Duration {
+        return time::Duration::from_millis(self.info_transmit_period_millisec);
+    }
+
+    pub fn cab_backup_period(&self) -> time::Duration {
+        return time::Duration::from_millis(self.cab_backup_period_millisec);
+    }
+
+    pub fn assign_retry_delay(&self) -> time::Duration {
+        return time::Duration::from_millis(self.assign_retry_millisec);
+    }
+
+    pub fn takeover_timeout(&self) -> time::Duration {
+        return time::Duration::from_millis(self.takeover_timeout_millisec);
+    }
+
+    pub fn hall_request_assigner_timeout(&self) -> time::Duration {
+        return time::Duration::from_millis(self.hall_request_assigner_timeout_millisec);
+    }
+
+    /// How often hall orders are moved to better cars, or `None` if they stay where they were assigned
+    pub fn reassign_period(&self) -> Option<time::Duration> {
+        if self.reassign_period_sec == 0 {
+            return None;
+        }
+        return Some(time::Duration::from_secs(self.reassign_period_sec));
+    }
+
+    pub fn get_served_floors(&self) -> Vec<u8> {
+        if self.served_floors.is_empty() {
+            return (0..self.num_floors).collect();
+        }
+        return self.served_floors.clone();
+    }
+
+    /// How long a car stays idle before it goes to its home floor, or `None` if parking is off
+    pub fn parking_idle_time(&self) -> Option<time::Duration> {
+        if self.parking_floors.is_empty() {
+            return None;
+        }
+        return Some(time::Duration::from_secs(self.parking_idle_time_sec));
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, err } => write!(f, "could not read config file {}: {}", path, err),
+            ConfigError::Parse { source, msg } => write!(f, "could not parse {}: {}", source, msg),
+            ConfigError::UnknownSetting(s) => write!(f, "unknown setting {}", s),
+            ConfigError::MissingValue(s) => write!(f, "missing value for {}", s),
+            ConfigError::Invalid(s) => write!(f, "invalid configuration: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<ConfigError> for io::Error {
+    fn from(err: ConfigError) -> io::Error {
+        return io::Error::new(io::ErrorKind::InvalidInput, err.to_string());
+    }
+}
+
+type ParsedArgs = (Option<String>, Vec<(String, String)>);
+
+/// Splits the arguments into an optional positional argument and a list of `(flag, value)` pairs.
+/// Both `--flag value` and `--flag=value` are accepted.
+pub(crate) fn parse_args(args: &[String]) -> Result<ParsedArgs, ConfigError> {
+    let mut positional: Option<String> = None;
+    let mut flags: Vec<(String, String)> = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.strip_prefix("--") {
+            Some(flag) => match flag.split_once('=') {
+                Some((key, value)) => flags.push((key.to_string(), value.to_string())),
+                None => match iter.next() {
+                    Some(value) => flags.push((flag.to_string(), value.clone())),
+                    None => return Err(ConfigError::MissingValue(arg.clone())),
+                },
+            },
+            None => {
+                if positional.is_some() {
+                    return Err(ConfigError::Invalid(format!("unexpected argument '{}'", arg)));
+                }
+                positional = Some(arg.clone());
+            }
+        }
+    }
+    return Ok((positional, flags));
+}
+
+fn read_file(path: &str) -> Result<serde_json::Map<String, serde_json::Value>, ConfigError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| ConfigError::Io { path: path.to_string(), err })?;
+    let parsed: Result<serde_json::Value, String> = if path.ends_with(".json") {
+        serde_json::from_str(&contents).map_err(|e| e.to_string())
+    } else {
+        toml::from_str(&contents).map_err(|e| e.to_string())
+    };
+    match parsed {
+        Ok(serde_json::Value::Object(map)) => Ok(map),
+        Ok(_) => Err(ConfigError::Parse { source: path.to_string(), msg: "expected a table of settings".to_string() }),
+        Err(msg) => Err(ConfigError::Parse { source: path.to_string(), msg }),
+    }
+}
+
+/// Parses a raw override so it gets the same type as the setting it replaces.
+fn parse_override(current: &serde_json::Value, raw: &str, source: &str) -> Result<serde_json::Value, ConfigError> {
+    match current {
+        serde_json::Value::String(_) => Ok(serde_json::Value::String(raw.to_string())),
+        _ => serde_json::from_str(raw)
+            .map_err(|e| ConfigError::Parse { source: source.to_string(), msg: e.to_string() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        return args.iter().map(|arg| arg.to_string()).collect();
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        return vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    }
+
+    /// A config file in the temp dir, removed when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &str) -> TempFile {
+            let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+            fs::write(&path, contents).unwrap();
+            return TempFile(path);
+        }
+
+        fn path(&self) -> &str {
+            return self.0.to_str().unwrap();
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn defaults_without_any_source() {
+        assert_eq!(Config::load(&[], vars(&[])).unwrap(), Config::default());
+    }
+
+    #[test]
+    fn file_then_environment_then_flags() {
+        let file = TempFile::new("precedence.toml",
+            "num_floors = 6\ndoor_open_time = 5\nmotor_timeout_duration_sec = 7\norder_clearing = \"in_direction\"\n");
+        let env = vars(&[
+            ("ELEVATOR_CONFIG", file.path()),
+            ("ELEVATOR_NUM_FLOORS", "8"),
+            ("ELEVATOR_DOOR_OPEN_TIME", "4"),
+            ("ELEVATOR_HW_SERVER", "10.0.0.2:15657"),
+            ("ELEVATOR_NOT_A_SETTING", "ignored"),
+            ("PATH", "/usr/bin"),
+        ]);
+        let config = Config::load(&args(&["--num-floors", "10", "--served-floors=[0,9]"]), env).unwrap();
+        assert_eq!(config.num_floors, 10);
+        assert_eq!(config.door_open_time, 4);
+        assert_eq!(config.motor_timeout_duration_sec, 7);
+        assert_eq!(config.order_clearing, ClearPolicy::InDirection);
+        assert_eq!(config.hw_server, "10.0.0.2:15657");
+        assert_eq!(config.served_floors, vec![0, 9]);
+    }
+
+    #[test]
+    fn config_flag_wins_over_the_environment() {
+        let from_env = TempFile::new("from_env.json", "{\"num_floors\": 5}");
+        let from_flag = TempFile::new("from_flag.json", "{\"num_floors\": 7}");
+        let config = Config::load(&args(&["--config", from_flag.path()]), vars(&[("ELEVATOR_CONFIG", from_env.path())])).unwrap();
+        assert_eq!(config.num_floors, 7);
+    }
+
+    #[test]
+    fn positional_argument_is_the_simulator_port() {
+        assert_eq!(Config::load(&args(&["15658"]), vars(&[])).unwrap().hw_server, "localhost:15658");
+        assert!(matches!(Config::load(&args(&["lift"]), vars(&[])), Err(ConfigError::Invalid(_))));
+        assert!(matches!(Config::load(&args(&["15658", "15659"]), vars(&[])), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn bad_values_are_rejected() {
+        let load = |a: &[&str], v: &[(&str, &str)]| Config::load(&args(a), vars(v));
+        assert!(matches!(load(&["--num-floors", "many"], &[]), Err(ConfigError::Parse { .. })));
+        assert!(matches!(load(&["--num-floors", "300"], &[]), Err(ConfigError::Parse { .. })));
+        assert!(matches!(load(&["--order-clearing", "sometimes"], &[]), Err(ConfigError::Parse { .. })));
+        assert!(matches!(load(&[], &[("ELEVATOR_ID", "two")]), Err(ConfigError::Parse { .. })));
+        assert!(matches!(load(&["--floors", "4"], &[]), Err(ConfigError::UnknownSetting(_))));
+        assert!(matches!(load(&["--id"], &[]), Err(ConfigError::MissingValue(_))));
+        assert!(matches!(load(&["--config", "/no/such/elevator.toml"], &[]), Err(ConfigError::Io { .. })));
+
+        let unknown = TempFile::new("unknown.toml", "floors = 4\n");
+        assert!(matches!(load(&["--config", unknown.path()], &[]), Err(ConfigError::UnknownSetting(_))));
+        let broken = TempFile::new("broken.toml", "num_floors = \n");
+        assert!(matches!(load(&["--config", broken.path()], &[]), Err(ConfigError::Parse { .. })));
+    }
+
+    #[test]
+    fn settings_we_can_not_run_with_are_invalid() {
+        let invalid = |config: Config| matches!(config.validate(), Err(ConfigError::Invalid(_)));
+        assert!(Config::default().validate().is_ok());
+        assert!(invalid(Config { num_floors: 1, ..Default::default() }));
+        assert!(invalid(Config { door_open_time: 0, ..Default::default() }));
+        assert!(invalid(Config { order_port: setting::ELEV_INFO_PORT, ..Default::default() }));
+        assert!(invalid(Config { info_transmit_period_millisec: setting::TIME_UNTIL_PEER_LOST_MILLISEC, ..Default::default() }));
+        assert!(invalid(Config { hw_server: String::new(), ..Default::default() }));
+
+        // Checked after every source is applied
+        assert!(matches!(Config::load(&args(&["--num-floors", "1"]), vars(&[])), Err(ConfigError::Invalid(_))));
+    }
+}
@@ -0,0 +1,53 @@
+//! Commands typed on stdin of a running elevator node, for the switches the hardware does not have:
+//!
+//! ```text
+//! recall <on|off>     building-wide fire service recall, seen by all nodes
+//! phase2 <on|off>     firefighter operation of this car, only during recall
+//! ```
+use std::io::BufRead;
+
+use crossbeam_channel as cbc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConsoleCommand {
+    Recall { active: bool },
+    PhaseTwo { active: bool },
+}
+
+impl std::str::FromStr for ConsoleCommand {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<ConsoleCommand, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["recall", on] => Ok(ConsoleCommand::Recall { active: parse_on_off(on)? }),
+            ["phase2", on] => Ok(ConsoleCommand::PhaseTwo { active: parse_on_off(on)? }),
+            _ => Err(format!("unknown command '{}'", line)),
+        }
+    }
+}
+
+/// Reads commands from stdin until it is closed, and sends them on `ch`.
+pub fn run(ch: cbc::Sender<ConsoleCommand>) {
+    for line in std::io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.parse::<ConsoleCommand>() {
+            Ok(cmd) => ch.send(cmd).unwrap(),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+fn parse_on_off(word: &str) -> Result<bool, String> {
+    match word {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("expected on or off, got '{}'", word)),
+    }
+}
@@ -1,4 +1,6 @@
-pub const ID: usize = 1;
+//! Default settings. These can be overridden at runtime, see `util/config.rs`.
+
+pub const HW_SERVER_PORT: u16 = 15657;
 
 pub const ORDER_PORT: u16 = 19839;
 pub const CAB_BACKUP_PORT: u16 = 19840;
@@ -6,10 +8,26 @@ pub const ELEV_INFO_PORT: u16 = 19841;
 
 pub const TIME_UNTIL_PEER_LOST_MILLISEC: u64 = 500;
 pub const INFO_TRANSMIT_PERIOD_MILLISEC: u64 = 15;
+pub const PHI_SUSPECT: f64 = 3.0;
+pub const PHI_LOST: f64 = 8.0;
+pub const HEARTBEAT_MIN_STD_DEV_MILLISEC: u64 = 25;
+pub const CAB_BACKUP_PERIOD_MILLISEC: u64 = 100;
+pub const ASSIGN_RETRIES: u32 = 3;
+pub const ASSIGN_RETRY_MILLISEC: u64 = 100;
+pub const TAKEOVER_TIMEOUT_MILLISEC: u64 = 1000;
+pub const REASSIGN_HYSTERESIS: u64 = 4;
+pub const HALL_REQUEST_ASSIGNER_TIMEOUT_MILLISEC: u64 = 200;
 
 pub const MOTOR_TIMEOUT_DURATION_SEC: u64 = 5;
 pub const OBSTRUCTED_TIME_BEFORE_REASSIGN_SEC: u64 = 10; 
 
 pub const ELEV_NUM_FLOORS: u8 = 4;
-pub const MAX_NUM_ELEV: usize = 3;
-pub const DOOR_OPEN_TIME: u64 = 3;
\ No newline at end of file
+pub const DOOR_OPEN_TIME: u64 = 3;
+pub const PARKING_IDLE_TIME_SEC: u64 = 20;
+pub const RECALL_FLOOR: u8 = 0;
+pub const STATE_DIR: &str = ".";
+
+pub const POLL_PERIOD_MILLISEC: u64 = 25;
+// The load jumps around while people step in and out
+pub const DEBOUNCE_LOAD_MILLISEC: u64 = 500;
+pub const FULL_LOAD_PERCENT: u8 = 80;
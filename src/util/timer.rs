@@ -0,0 +1,62 @@
+//! Any number of named timers, run from a single thread that sleeps until the next one expires.
+//!
+//! # Example
+//! ```rust
+//! use crossbeam_channel as cbc;
+//! use std::time::Duration;
+//! use elevator::util::timer::{self, TimerRequest};
+//!
+//! let (request_tx, request_rx) = cbc::unbounded();
+//! let (expired_tx, expired_rx) = cbc::unbounded();
+//! std::thread::spawn(move || timer::run(request_rx, expired_tx));
+//!
+//! request_tx.send(TimerRequest::Start { name: "door", duration: Duration::from_millis(10) }).unwrap();
+//! assert_eq!(expired_rx.recv().unwrap(), "door");
+//! ```
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel as cbc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimerRequest<K> {
+    /// Starts the timer, or restarts it if it is already running
+    Start { name: K, duration: Duration },
+    Cancel { name: K },
+}
+
+/// Runs the timers until `requests` is disconnected.
+/// The name of a timer is sent on `expired` once every time it runs out.
+pub fn run<K: Copy + Eq + Hash>(requests: cbc::Receiver<TimerRequest<K>>, expired: cbc::Sender<K>) {
+    let mut deadlines: HashMap<K, Instant> = HashMap::new();
+    loop {
+        let request = match deadlines.values().min() {
+            Some(&deadline) => requests.recv_deadline(deadline),
+            None => requests.recv().map_err(|_| cbc::RecvTimeoutError::Disconnected),
+        };
+        match request {
+            Ok(TimerRequest::Start { name, duration }) => {
+                deadlines.insert(name, Instant::now() + duration);
+            }
+            Ok(TimerRequest::Cancel { name }) => {
+                deadlines.remove(&name);
+            }
+            Err(cbc::RecvTimeoutError::Timeout) => {
+                let now = Instant::now();
+                let mut due: Vec<(Instant, K)> = deadlines.iter()
+                    .filter(|(_, &deadline)| deadline <= now)
+                    .map(|(&name, &deadline)| (deadline, name))
+                    .collect();
+                due.sort_by_key(|(deadline, _)| *deadline);
+                for (_, name) in due {
+                    deadlines.remove(&name);
+                    if expired.send(name).is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(cbc::RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}